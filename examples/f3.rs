@@ -22,7 +22,7 @@ use f3::hal::prelude::*;
 use f3::hal::stm32f30x;
 use f3::hal::time::MonoTimer;
 use f3::led::Leds;
-use hc_sr04::{HcSr04, Error};
+use hc_sr04::{consts, HcSr04, Error, SensorError};
 use rtfm::{app, Resource, Threshold};
 
 app! {
@@ -95,14 +95,15 @@ fn idle(t: &mut Threshold, mut r: idle::Resources) -> ! {
                 iprintln!(_stim, "{:?}", cm);
                 // How many LEDs should we turn on:
                 let num_leds = {
-                    if cm <= 2 {
-                        // 2cm is the smallest distance the sensor will
-                        // reliably report so use that as minimum
+                    let min_cm = consts::MIN_RANGE_MM / 10;
+                    if cm <= min_cm {
+                        // Smallest distance the sensor will reliably report
+                        // so use that as minimum
                         r.LEDS.len()
                     } else if cm >= 10 {
                         0
                     } else {
-                        r.LEDS.len() - (cm as usize - 2)
+                        r.LEDS.len() - (cm - min_cm) as usize
                     }
                 };
                 r.LEDS.iter_mut()
@@ -121,7 +122,20 @@ fn idle(t: &mut Threshold, mut r: idle::Resources) -> ! {
                 // measurement and cause further interrupts.
                 rtfm::wfi();
             }
-            Err(_) => unreachable!(),
+            Err(Error::Other(SensorError::NoObjectDetected { .. })) => {
+                // Nothing within range of the sensor
+                r.LEDS.iter_mut()
+                    .for_each(|l| l.off());
+            }
+            Err(Error::Other(SensorError::ImplausiblePulse { ticks })) => {
+                // Log the raw pulse width to help find the cause
+                iprintln!(_stim, "Implausible pulse of {} ticks", ticks);
+            }
+            Err(Error::Other(e)) => {
+                // Measurement failed, report the error and let the next
+                // iteration trigger a new measurement
+                iprintln!(_stim, "{:?}", e);
+            }
         }
     }
 }
@@ -129,8 +143,12 @@ fn idle(t: &mut Threshold, mut r: idle::Resources) -> ! {
 // Function to notify sensor of external interrupt. If setup correctly
 // the interrupt should occur once the echo pin is pulled high or low.
 fn update(_t: &mut Threshold, mut r: EXTI15_10::Resources) {
-    r.SENSOR
-        .update()
-        .expect("Interrupt function called while sensor were in wrong state!");
+    match r.SENSOR.update() {
+        // After a timeout or a stuck echo the sensor has already moved on
+        // when the late edge of that pulse arrives, this is harmless so the
+        // edge is ignored
+        Ok(()) | Err(SensorError::WrongMode) => {}
+        Err(e) => panic!("Unexpected sensor error in interrupt: {:?}", e),
+    }
     r.EXTI.pr1.write(|w| w.pr15().set_bit());
 }
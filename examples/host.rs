@@ -0,0 +1,59 @@
+//! Example running the `hc-sr04` driver on a desktop.
+//!
+//! Without a sensor attached the edges of the return pulse are simulated by
+//! calling `update` after sleeping for the width of the pulse. Run with
+//! `cargo run --example host --features std`.
+
+#![deny(warnings)]
+
+extern crate embedded_hal as hal;
+extern crate hc_sr04;
+
+use std::thread;
+use std::time::Duration;
+use hal::digital::OutputPin;
+use hc_sr04::builder::HcSr04Builder;
+use hc_sr04::filter::Median;
+use hc_sr04::host::{StdClock, StdDelay};
+
+/// Trigger pin which is not connected to anything
+struct NoPin;
+
+impl OutputPin for NoPin {
+    fn is_high(&self) -> bool {
+        false
+    }
+
+    fn is_low(&self) -> bool {
+        true
+    }
+
+    fn set_low(&mut self) {}
+
+    fn set_high(&mut self) {}
+}
+
+fn main() {
+    let mut sensor = HcSr04Builder::new(NoPin, StdDelay, StdClock::new())
+        .filter(Median::<3>::new())
+        .build()
+        .expect("Invalid configuration");
+    // Width in microseconds of the simulated return pulses, the third is an
+    // outlier removed by the filter
+    for &echo_us in &[5_800, 5_900, 11_600, 6_000, 6_100] {
+        // Trigger a new measurement
+        let _ = sensor.distance();
+        // Rising edge of the return pulse shortly after the trigger
+        thread::sleep(Duration::from_micros(500));
+        sensor.update().unwrap();
+        // Falling edge
+        thread::sleep(Duration::from_micros(echo_us));
+        sensor.update().unwrap();
+        match sensor.distance() {
+            Ok(dist) => println!("Distance: {} mm", dist.mm()),
+            Err(err) => println!("Measurement failed: {:?}", err),
+        }
+        // Wait out the measurement cycle of the sensor
+        thread::sleep(Duration::from_millis(60));
+    }
+}
@@ -0,0 +1,395 @@
+//! Builder validating the configuration of a driver up front.
+//!
+//! Misconfiguring the driver through the individual setters of `HcSr04` is
+//! not caught until readings come out wrong. `HcSr04Builder` collects the
+//! configuration and checks it as a whole when the driver is built:
+//!
+//! ```ignore
+//! let sensor = HcSr04Builder::new(trigger, delay, timer)
+//!     .temperature(5)
+//!     .max_distance(Distance::from_echo_us(11_600, DEFAULT_SPEED_FACTOR))
+//!     .build()?;
+//! ```
+//!
+//! The builder can also hand the echo pin to the driver with
+//! `HcSr04Builder::echo`, so that the methods reading the pin don't need it
+//! passed each time, give it a filter for its readings with
+//! `HcSr04Builder::filter` and a timer to abort measurements with
+//! `HcSr04Builder::timeout`.
+
+use hal::blocking::delay::DelayUs;
+use hal::digital::{InputPin, OutputPin};
+use hal::timer::CountDown;
+use stm32f30x_hal::time::{Hertz, MonoTimer};
+use filter::{Filter, NoFilter};
+use {consts, Clock, Distance, HcSr04, NoEcho, NoTimeout, TEMPERATURE_RANGE};
+
+/// Reasons a configuration is rejected by `HcSr04Builder::build`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The `Clock` or the capture frequency runs at 0 Hz
+    ZeroFrequency,
+    /// The shortest valid return pulse is not shorter than the longest
+    InvalidPulseLimits,
+    /// The maximum distance is not beyond the minimum range of the sensor
+    InvalidRange,
+    /// The temperature is outside of the range the driver compensates for,
+    /// -100°C to 200°C
+    TemperatureOutOfRange,
+}
+
+/// Builder for `HcSr04` which validates the configuration.
+///
+/// Settings which are not given keep the defaults of `HcSr04::new`.
+pub struct HcSr04Builder<
+    Pin,
+    Delay,
+    Clk = MonoTimer,
+    EchoPin = NoEcho,
+    Filt = NoFilter,
+    Timeout = NoTimeout,
+> where
+    Timeout: CountDown,
+{
+    /// Output pin to trigger sensor
+    trigger: Pin,
+    /// Input pin connected to the echo of the sensor
+    echo: EchoPin,
+    /// Filter applied to the readings of the driver
+    filter: Filt,
+    /// Delay to wait on for sensor trigger
+    delay: Delay,
+    /// Timer to estimate returning pulse width
+    timer: Clk,
+    /// Timer to abort measurements which take too long
+    timeout: Timeout,
+    /// Time to wait before abandoning a measurement
+    timeout_period: Timeout::Time,
+    /// Frequency of the external timer used for captured timestamps
+    capture_hz: Option<u32>,
+    /// Shortest and longest valid return pulse in microseconds
+    pulse_limits: Option<(u32, u32)>,
+    /// Largest distance considered plausible
+    max_distance: Option<Distance>,
+    /// Temperature of the air in °C
+    temperature: Option<i16>,
+    /// Minimum time in microseconds between edges of the return pulse
+    debounce_us: Option<u32>,
+    /// Only trigger the sensor on explicit request, never from `distance`
+    manual_trigger: bool,
+}
+
+impl<Pin, Delay, Clk> HcSr04Builder<Pin, Delay, Clk>
+where
+    Pin: OutputPin,
+    Delay: DelayUs<u32>,
+    Clk: Clock,
+{
+    /// Start building a driver, the arguments are the same as for
+    /// `HcSr04::new`.
+    pub fn new(trigger: Pin, delay: Delay, timer: Clk) -> Self {
+        HcSr04Builder {
+            trigger: trigger,
+            echo: NoEcho,
+            filter: NoFilter,
+            delay: delay,
+            timer: timer,
+            timeout: NoTimeout,
+            timeout_period: (),
+            capture_hz: None,
+            pulse_limits: None,
+            max_distance: None,
+            temperature: None,
+            debounce_us: None,
+            manual_trigger: false,
+        }
+    }
+}
+
+impl<Pin, Delay, Clk, EchoPin, Filt, Timeout> HcSr04Builder<Pin, Delay, Clk, EchoPin, Filt, Timeout>
+where
+    Pin: OutputPin,
+    Delay: DelayUs<u32>,
+    Clk: Clock,
+    Filt: Filter,
+    Timeout: CountDown,
+    Timeout::Time: Copy,
+{
+    /// Hand the echo pin to the driver.
+    ///
+    /// The driver then reads the pin itself, making `HcSr04::update_echo`,
+    /// `HcSr04::echo_noise_floor`, `HcSr04::measure_echo`,
+    /// `HcSr04::measure_echo_samples` and `HcSr04::echo_connected`
+    /// available. These behave the same as `update_from_pin`,
+    /// `measure_noise_floor`, `measure_with_delay`, `measure_samples` and
+    /// `is_connected`, which take the pin as argument and remain available
+    /// either way.
+    pub fn echo<E>(self, echo: E) -> HcSr04Builder<Pin, Delay, Clk, E, Filt, Timeout>
+    where
+        E: InputPin,
+    {
+        HcSr04Builder {
+            trigger: self.trigger,
+            echo: echo,
+            filter: self.filter,
+            delay: self.delay,
+            timer: self.timer,
+            timeout: self.timeout,
+            timeout_period: self.timeout_period,
+            capture_hz: self.capture_hz,
+            pulse_limits: self.pulse_limits,
+            max_distance: self.max_distance,
+            temperature: self.temperature,
+            debounce_us: self.debounce_us,
+            manual_trigger: self.manual_trigger,
+        }
+    }
+
+    /// Filter the readings of the driver with `filter`.
+    ///
+    /// Readings returned from `HcSr04::distance` are then passed through
+    /// `filter` first, e.g. a `Median` to remove outliers. The unfiltered
+    /// measurements remain available, see `HcSr04::distance`. Without this
+    /// readings are returned unfiltered.
+    pub fn filter<F>(self, filter: F) -> HcSr04Builder<Pin, Delay, Clk, EchoPin, F, Timeout>
+    where
+        F: Filter,
+    {
+        HcSr04Builder {
+            trigger: self.trigger,
+            echo: self.echo,
+            filter: filter,
+            delay: self.delay,
+            timer: self.timer,
+            timeout: self.timeout,
+            timeout_period: self.timeout_period,
+            capture_hz: self.capture_hz,
+            pulse_limits: self.pulse_limits,
+            max_distance: self.max_distance,
+            temperature: self.temperature,
+            debounce_us: self.debounce_us,
+            manual_trigger: self.manual_trigger,
+        }
+    }
+
+    /// Abort measurements after a timeout, see `HcSr04::with_timeout`.
+    ///
+    /// The `timeout` timer is started with `period` every time the sensor is
+    /// triggered and the measurement is abandoned if it expires first.
+    /// Without this measurements are only abandoned once the echo line is
+    /// considered stuck, see `HcSr04::distance`.
+    pub fn timeout<T>(
+        self,
+        timeout: T,
+        period: T::Time,
+    ) -> HcSr04Builder<Pin, Delay, Clk, EchoPin, Filt, T>
+    where
+        T: CountDown,
+        T::Time: Copy,
+    {
+        HcSr04Builder {
+            trigger: self.trigger,
+            echo: self.echo,
+            filter: self.filter,
+            delay: self.delay,
+            timer: self.timer,
+            timeout: timeout,
+            timeout_period: period,
+            capture_hz: self.capture_hz,
+            pulse_limits: self.pulse_limits,
+            max_distance: self.max_distance,
+            temperature: self.temperature,
+            debounce_us: self.debounce_us,
+            manual_trigger: self.manual_trigger,
+        }
+    }
+
+    /// Set the frequency of captured timestamps, see
+    /// `HcSr04::set_capture_frequency`.
+    pub fn capture_frequency(mut self, hz: Hertz) -> Self {
+        self.capture_hz = Some(hz.0);
+        self
+    }
+
+    /// Set the shortest and longest valid return pulse, see
+    /// `HcSr04::set_pulse_limits`.
+    pub fn pulse_limits(mut self, min_us: u32, max_us: u32) -> Self {
+        self.pulse_limits = Some((min_us, max_us));
+        self
+    }
+
+    /// Set the largest distance considered plausible, see
+    /// `HcSr04::set_max_distance`.
+    pub fn max_distance(mut self, max: Distance) -> Self {
+        self.max_distance = Some(max);
+        self
+    }
+
+    /// Set the temperature of the air in °C, see `HcSr04::set_temperature`.
+    pub fn temperature(mut self, celsius: i16) -> Self {
+        self.temperature = Some(celsius);
+        self
+    }
+
+    /// Set the debounce time of the return pulse, see
+    /// `HcSr04::set_debounce`.
+    pub fn debounce(mut self, us: u32) -> Self {
+        self.debounce_us = Some(us);
+        self
+    }
+
+    /// Choose whether the sensor is only triggered on explicit request, see
+    /// `HcSr04::set_manual_trigger`.
+    pub fn manual_trigger(mut self, enable: bool) -> Self {
+        self.manual_trigger = enable;
+        self
+    }
+
+    /// Validate the configuration and create the driver.
+    ///
+    /// # Errors
+    /// - `ConfigError::ZeroFrequency` if the `Clock` or the capture
+    /// frequency is 0 Hz.
+    /// - `ConfigError::InvalidPulseLimits` if the shortest valid return
+    /// pulse is not shorter than the longest.
+    /// - `ConfigError::InvalidRange` if the maximum distance is not beyond
+    /// `consts::MIN_RANGE_MM`.
+    /// - `ConfigError::TemperatureOutOfRange` if the temperature is outside
+    /// of -100°C to 200°C, where it would otherwise be clamped.
+    pub fn build(self) -> Result<HcSr04<Pin, Delay, Timeout, Clk, EchoPin, Filt>, ConfigError> {
+        if self.timer.frequency() == 0 || self.capture_hz == Some(0) {
+            return Err(ConfigError::ZeroFrequency);
+        }
+        if let Some((min_us, max_us)) = self.pulse_limits {
+            if min_us >= max_us {
+                return Err(ConfigError::InvalidPulseLimits);
+            }
+        }
+        if let Some(max) = self.max_distance {
+            if max.mm() <= consts::MIN_RANGE_MM {
+                return Err(ConfigError::InvalidRange);
+            }
+        }
+        if let Some(celsius) = self.temperature {
+            if celsius < TEMPERATURE_RANGE.0 || celsius > TEMPERATURE_RANGE.1 {
+                return Err(ConfigError::TemperatureOutOfRange);
+            }
+        }
+        let mut sensor = HcSr04::from_parts(
+            self.trigger,
+            self.delay,
+            self.timer,
+            self.timeout,
+            self.timeout_period,
+            self.echo,
+            self.filter,
+        );
+        if let Some(hz) = self.capture_hz {
+            sensor.set_capture_frequency(Hertz(hz));
+        }
+        if let Some((min_us, max_us)) = self.pulse_limits {
+            sensor.set_pulse_limits(min_us, max_us);
+        }
+        sensor.set_max_distance(self.max_distance);
+        if let Some(celsius) = self.temperature {
+            sensor.set_temperature(celsius);
+        }
+        if let Some(us) = self.debounce_us {
+            sensor.set_debounce(us);
+        }
+        sensor.set_manual_trigger(self.manual_trigger);
+        Ok(sensor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use mock::{MockDelay, MockPin, MockTimeout};
+    use {Error, MockClock};
+
+    /// Start building a driver timed by `clock`
+    fn builder(clock: &MockClock) -> HcSr04Builder<MockPin, MockDelay, &MockClock> {
+        HcSr04Builder::new(MockPin { high: false }, MockDelay { clock: clock }, clock)
+    }
+
+    #[test]
+    fn valid_configuration() {
+        let clock = MockClock::new(1_000_000);
+        let sensor = builder(&clock)
+            .capture_frequency(Hertz(8_000_000))
+            .pulse_limits(100, 20_000)
+            .max_distance(Distance(3_000))
+            .temperature(5)
+            .debounce(50)
+            .build()
+            .ok()
+            .unwrap();
+        assert_eq!(sensor.temperature(), Some(5));
+        assert_eq!(sensor.config().frequency_hz, 8_000_000);
+        assert_eq!(sensor.config().pulse_limits_us, (100, 20_000));
+        assert_eq!(sensor.config().max_range_mm, 3_000);
+        assert_eq!(sensor.config().debounce_us, 50);
+    }
+
+    #[test]
+    fn zero_frequency() {
+        let clock = MockClock::new(0);
+        assert_eq!(builder(&clock).build().err(), Some(ConfigError::ZeroFrequency));
+        let clock = MockClock::new(1_000_000);
+        let result = builder(&clock).capture_frequency(Hertz(0)).build();
+        assert_eq!(result.err(), Some(ConfigError::ZeroFrequency));
+    }
+
+    #[test]
+    fn invalid_pulse_limits() {
+        let clock = MockClock::new(1_000_000);
+        let result = builder(&clock).pulse_limits(500, 500).build();
+        assert_eq!(result.err(), Some(ConfigError::InvalidPulseLimits));
+        let result = builder(&clock).pulse_limits(600, 500).build();
+        assert_eq!(result.err(), Some(ConfigError::InvalidPulseLimits));
+    }
+
+    #[test]
+    fn invalid_range() {
+        let clock = MockClock::new(1_000_000);
+        let result = builder(&clock).max_distance(Distance(consts::MIN_RANGE_MM)).build();
+        assert_eq!(result.err(), Some(ConfigError::InvalidRange));
+    }
+
+    #[test]
+    fn temperature_out_of_range() {
+        let clock = MockClock::new(1_000_000);
+        let result = builder(&clock).temperature(TEMPERATURE_RANGE.1 + 1).build();
+        assert_eq!(result.err(), Some(ConfigError::TemperatureOutOfRange));
+        let result = builder(&clock).temperature(TEMPERATURE_RANGE.0 - 1).build();
+        assert_eq!(result.err(), Some(ConfigError::TemperatureOutOfRange));
+    }
+
+    #[test]
+    fn timeout() {
+        let clock = MockClock::new(1_000_000);
+        let expired = Cell::new(false);
+        let mut sensor = builder(&clock)
+            .timeout(MockTimeout { expired: &expired }, ())
+            .build()
+            .ok()
+            .unwrap();
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        assert_eq!(sensor.state_code(), 1);
+        expired.set(true);
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        assert_eq!(sensor.state_code(), 0);
+    }
+
+    #[test]
+    fn manual_trigger() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = builder(&clock).manual_trigger(true).build().ok().unwrap();
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        assert_eq!(sensor.state_code(), 0);
+        sensor.fire().unwrap();
+        assert_eq!(sensor.state_code(), 1);
+    }
+}
@@ -0,0 +1,28 @@
+//! Constants from the [`HC-SR04` datasheet][1].
+//!
+//! [1]: http://www.micropik.com/PDF/HCSR04.pdf
+
+/// Smallest distance in millimeters the sensor reliably measures.
+pub const MIN_RANGE_MM: u32 = 20;
+
+/// Largest distance in millimeters the sensor reliably measures.
+pub const MAX_RANGE_MM: u32 = 4_000;
+
+/// Width in microseconds of the pulse needed to trigger a measurement.
+pub const TRIGGER_WIDTH_US: u32 = 10;
+
+/// Shortest return pulse in microseconds of a valid reading, produced by an
+/// object at `MIN_RANGE_MM`.
+pub const MIN_VALID_ECHO_US: u32 = 116;
+
+/// Longest return pulse in microseconds of a valid reading, produced by an
+/// object at `MAX_RANGE_MM`.
+pub const MAX_VALID_ECHO_US: u32 = 23_200;
+
+/// Longest return pulse in microseconds, produced when no object is
+/// detected.
+pub const MAX_ECHO_US: u32 = 38_000;
+
+/// Recommended time in microseconds from the start of one measurement to the
+/// next, to avoid picking up the echo of the previous measurement.
+pub const MEASUREMENT_CYCLE_US: u32 = 60_000;
@@ -0,0 +1,561 @@
+//! Filters for smoothing a series of readings from the sensor.
+//!
+//! All filters implement `Filter` and can be combined into a pipeline with
+//! `Filter::chain`, e.g. a median filter to remove outliers followed by a
+//! moving average:
+//!
+//! ```ignore
+//! let mut filter = Median::<5>::new().chain(MovingAverage::<4>::new());
+//! let smooth = filter.update(dist);
+//! ```
+
+use Distance;
+
+/// Common interface of all filters.
+pub trait Filter {
+    /// Add a new reading to the filter and return the filtered distance.
+    fn update(&mut self, distance: Distance) -> Distance;
+
+    /// Forget all previous readings.
+    fn reset(&mut self);
+
+    /// Add a new reading taken `elapsed_ms` milliseconds after the previous
+    /// one and return the filtered distance.
+    ///
+    /// An `HcSr04` with a filter attached calls this for every reading but
+    /// the first, measuring the time with its `Clock`. Only `ResetOnGap`
+    /// makes use of the time, the default ignores it and calls `update`.
+    fn update_after(&mut self, distance: Distance, _elapsed_ms: u32) -> Distance {
+        self.update(distance)
+    }
+
+    /// Feed the output of this filter into `next`.
+    fn chain<F>(self, next: F) -> Chain<Self, F>
+    where
+        Self: Sized,
+        F: Filter,
+    {
+        Chain {
+            first: self,
+            second: next,
+        }
+    }
+}
+
+/// Filter returning every reading unchanged.
+///
+/// This is the filter of an `HcSr04` built without
+/// `HcSr04Builder::filter`.
+pub struct NoFilter;
+
+impl Filter for NoFilter {
+    fn update(&mut self, distance: Distance) -> Distance {
+        distance
+    }
+
+    fn reset(&mut self) {}
+}
+
+/// Two filters applied one after the other, see `Filter::chain`.
+pub struct Chain<A, B> {
+    /// Filter applied to the readings
+    first: A,
+    /// Filter applied to the output of `first`
+    second: B,
+}
+
+impl<A, B> Filter for Chain<A, B>
+where
+    A: Filter,
+    B: Filter,
+{
+    fn update(&mut self, distance: Distance) -> Distance {
+        let distance = self.first.update(distance);
+        self.second.update(distance)
+    }
+
+    fn update_after(&mut self, distance: Distance, elapsed_ms: u32) -> Distance {
+        let distance = self.first.update_after(distance, elapsed_ms);
+        self.second.update_after(distance, elapsed_ms)
+    }
+
+    fn reset(&mut self) {
+        self.first.reset();
+        self.second.reset();
+    }
+}
+
+/// Restart a filter when readings resume after a gap.
+///
+/// After a pause in readings, e.g. while the sensor was idle or failing to
+/// measure, the state of a filter is stale and would pull the first fresh
+/// readings towards old values. This wraps a filter and resets it when more
+/// than `max_gap_ms` milliseconds have passed since the previous reading, so
+/// that the first reading after the gap seeds the filter and is returned
+/// unfiltered. All filters in this module return the first reading after a
+/// reset as is.
+///
+/// The time of each reading is supplied by the caller with `update_at`, from
+/// any millisecond counter which may wrap. Attached to an `HcSr04` with
+/// `HcSr04Builder::filter` the driver supplies the time between readings
+/// through `Filter::update_after` instead. `Filter::update` passes readings
+/// to the wrapped filter without checking for gaps.
+pub struct ResetOnGap<F> {
+    /// Wrapped filter
+    inner: F,
+    /// Longest time in milliseconds between readings without a reset
+    max_gap_ms: u32,
+    /// Time in milliseconds of the previous reading
+    last_ms: Option<u32>,
+}
+
+impl<F> ResetOnGap<F>
+where
+    F: Filter,
+{
+    /// Wrap `inner`, resetting it after gaps longer than `max_gap_ms`.
+    pub fn new(inner: F, max_gap_ms: u32) -> Self {
+        ResetOnGap {
+            inner: inner,
+            max_gap_ms: max_gap_ms,
+            last_ms: None,
+        }
+    }
+
+    /// Set the longest time in milliseconds between readings without a
+    /// reset.
+    pub fn set_max_gap(&mut self, max_gap_ms: u32) {
+        self.max_gap_ms = max_gap_ms;
+    }
+
+    /// Add a new reading taken at `now_ms` and return the filtered distance.
+    pub fn update_at(&mut self, distance: Distance, now_ms: u32) -> Distance {
+        if let Some(last) = self.last_ms {
+            if now_ms.wrapping_sub(last) > self.max_gap_ms {
+                self.inner.reset();
+            }
+        }
+        self.last_ms = Some(now_ms);
+        self.inner.update(distance)
+    }
+}
+
+impl<F> Filter for ResetOnGap<F>
+where
+    F: Filter,
+{
+    fn update(&mut self, distance: Distance) -> Distance {
+        self.inner.update(distance)
+    }
+
+    fn update_after(&mut self, distance: Distance, elapsed_ms: u32) -> Distance {
+        if elapsed_ms > self.max_gap_ms {
+            self.inner.reset();
+        }
+        self.inner.update_after(distance, elapsed_ms)
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+        self.last_ms = None;
+    }
+}
+
+/// Fixed size buffer of the last `N` readings in millimeters.
+pub(crate) struct Window<const N: usize> {
+    /// Readings, only the first `len` are valid
+    values: [u32; N],
+    /// Position the next reading is written to
+    next: usize,
+    /// Number of valid readings
+    len: usize,
+}
+
+impl<const N: usize> Window<N> {
+    /// Create an empty window
+    pub(crate) fn new() -> Self {
+        assert!(N > 0, "Filter window must not be empty");
+        Window {
+            values: [0; N],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    /// Add a reading, returning the reading it replaced if the window was
+    /// full
+    pub(crate) fn push(&mut self, mm: u32) -> Option<u32> {
+        let old = if self.len == N {
+            Some(self.values[self.next])
+        } else {
+            self.len += 1;
+            None
+        };
+        self.values[self.next] = mm;
+        self.next = (self.next + 1) % N;
+        old
+    }
+
+    /// Valid readings in no particular order
+    pub(crate) fn values(&self) -> &[u32] {
+        &self.values[..self.len]
+    }
+
+    /// Forget all readings
+    pub(crate) fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+    }
+}
+
+/// Median of the last `N` readings.
+///
+/// Good at removing single outliers without smearing sudden changes. Until
+/// `N` readings have been added the median of the available readings is
+/// returned. For an even number of readings the mean of the two middle
+/// readings is used.
+pub struct Median<const N: usize> {
+    /// Last `N` readings
+    window: Window<N>,
+}
+
+impl<const N: usize> Median<N> {
+    /// Create a new empty median filter.
+    pub fn new() -> Self {
+        Median { window: Window::new() }
+    }
+}
+
+impl<const N: usize> Default for Median<N> {
+    fn default() -> Self {
+        Median::new()
+    }
+}
+
+impl<const N: usize> Filter for Median<N> {
+    fn update(&mut self, distance: Distance) -> Distance {
+        self.window.push(distance.mm());
+        let mut sorted = [0; N];
+        let len = self.window.values().len();
+        sorted[..len].copy_from_slice(self.window.values());
+        sorted[..len].sort_unstable();
+        let median = if len % 2 == 1 {
+            sorted[len / 2]
+        } else {
+            ((sorted[len / 2 - 1] as u64 + sorted[len / 2] as u64) / 2) as u32
+        };
+        Distance(median)
+    }
+
+    fn reset(&mut self) {
+        self.window.clear();
+    }
+}
+
+/// Mean of the last `N` readings.
+///
+/// Until `N` readings have been added the mean of the available readings is
+/// returned.
+pub struct MovingAverage<const N: usize> {
+    /// Last `N` readings
+    window: Window<N>,
+    /// Sum of all readings in `window`
+    sum: u64,
+}
+
+impl<const N: usize> MovingAverage<N> {
+    /// Create a new empty moving average filter.
+    pub fn new() -> Self {
+        MovingAverage {
+            window: Window::new(),
+            sum: 0,
+        }
+    }
+}
+
+impl<const N: usize> Default for MovingAverage<N> {
+    fn default() -> Self {
+        MovingAverage::new()
+    }
+}
+
+impl<const N: usize> Filter for MovingAverage<N> {
+    fn update(&mut self, distance: Distance) -> Distance {
+        if let Some(old) = self.window.push(distance.mm()) {
+            self.sum -= old as u64;
+        }
+        self.sum += distance.mm() as u64;
+        Distance((self.sum / self.window.values().len() as u64) as u32)
+    }
+
+    fn reset(&mut self) {
+        self.window.clear();
+        self.sum = 0;
+    }
+}
+
+/// Mean of the last `N` readings weighted by their confidence.
+///
+/// Each reading is added with `update_weighted` together with a confidence
+/// from `0` (no confidence) to `255` (full confidence), e.g. lower for
+/// readings close to the limits of the sensor or taken in noisy conditions.
+/// The output is `sum(reading * confidence) / sum(confidence)` over the
+/// window, so marginal readings contribute less than a plain moving average
+/// would let them. If every reading in the window has zero confidence the
+/// plain mean is returned. `Filter::update` adds readings with full
+/// confidence.
+pub struct WeightedAverage<const N: usize> {
+    /// Last `N` readings
+    window: Window<N>,
+    /// Confidence of each reading in `window`, in the same order
+    weights: Window<N>,
+}
+
+impl<const N: usize> WeightedAverage<N> {
+    /// Create a new empty weighted average filter.
+    pub fn new() -> Self {
+        WeightedAverage {
+            window: Window::new(),
+            weights: Window::new(),
+        }
+    }
+
+    /// Add a new reading with the given confidence and return the filtered
+    /// distance.
+    pub fn update_weighted(&mut self, distance: Distance, confidence: u8) -> Distance {
+        self.window.push(distance.mm());
+        self.weights.push(confidence as u32);
+        let values = self.window.values();
+        let weights = self.weights.values();
+        let total: u64 = weights.iter().map(|&w| w as u64).sum();
+        if total == 0 {
+            let sum: u64 = values.iter().map(|&v| v as u64).sum();
+            return Distance((sum / values.len() as u64) as u32);
+        }
+        let sum: u64 = values
+            .iter()
+            .zip(weights)
+            .map(|(&v, &w)| v as u64 * w as u64)
+            .sum();
+        Distance((sum / total) as u32)
+    }
+}
+
+impl<const N: usize> Default for WeightedAverage<N> {
+    fn default() -> Self {
+        WeightedAverage::new()
+    }
+}
+
+impl<const N: usize> Filter for WeightedAverage<N> {
+    fn update(&mut self, distance: Distance) -> Distance {
+        self.update_weighted(distance, u8::max_value())
+    }
+
+    fn reset(&mut self) {
+        self.window.clear();
+        self.weights.clear();
+    }
+}
+
+/// Hold the output until a reading moves more than a threshold away.
+///
+/// A stationary target jitters by a few millimeters between readings. This
+/// filter keeps returning the same distance until a reading differs from it
+/// by more than `threshold_mm`, at which point the output jumps to the new
+/// reading. This gives a steady display of static targets while still
+/// following real movement, but movements smaller than the threshold are
+/// never shown. The first reading is returned as is.
+pub struct DeadBand {
+    /// Largest change in millimeters which is suppressed
+    threshold_mm: u32,
+    /// Distance currently returned
+    output: Option<Distance>,
+}
+
+impl DeadBand {
+    /// Create a new dead band filter suppressing changes of up to
+    /// `threshold_mm` millimeters.
+    pub fn new(threshold_mm: u32) -> Self {
+        DeadBand {
+            threshold_mm: threshold_mm,
+            output: None,
+        }
+    }
+}
+
+impl Filter for DeadBand {
+    fn update(&mut self, distance: Distance) -> Distance {
+        let output = match self.output {
+            Some(held) if held.approx_eq(distance, self.threshold_mm) => held,
+            _ => distance,
+        };
+        self.output = Some(output);
+        output
+    }
+
+    fn reset(&mut self) {
+        self.output = None;
+    }
+}
+
+/// One dimensional Kalman filter assuming a constant distance.
+///
+/// Compared to a moving average this rejects noise better while still
+/// following a moving target, how closely is decided by the two tunable
+/// parameters:
+///
+/// - `process_noise` is how much the real distance is expected to change
+/// between two readings, as a variance in mm². Larger values follow moving
+/// targets faster.
+/// - `measurement_noise` is the noise of the sensor, as a variance in mm².
+/// Larger values give a smoother output. The sensor is typically accurate to
+/// a few millimeters, e.g. a value of `9` for ±3 mm.
+///
+/// All calculations use integer math, the estimate is kept with a resolution
+/// of 1/256 mm internally.
+pub struct SimpleKalman {
+    /// Variance of the change in distance between readings, in mm²
+    process_noise: u32,
+    /// Variance of the readings, in mm²
+    measurement_noise: u32,
+    /// Current estimate in 1/256 mm and its variance in mm²
+    state: Option<(i64, u64)>,
+}
+
+impl SimpleKalman {
+    /// Create a new filter with the given noise parameters.
+    pub fn new(process_noise: u32, measurement_noise: u32) -> Self {
+        SimpleKalman {
+            process_noise: process_noise,
+            measurement_noise: measurement_noise,
+            state: None,
+        }
+    }
+}
+
+impl Filter for SimpleKalman {
+    fn update(&mut self, distance: Distance) -> Distance {
+        let z = (distance.mm() as i64) << 8;
+        let r = self.measurement_noise as u64;
+        let (x, p) = match self.state {
+            // Predict, the distance is assumed constant while its
+            // uncertainty grows with the process noise
+            Some((x, p)) => {
+                let p = p + self.process_noise as u64;
+                // Kalman gain in Q16
+                let k = if p + r == 0 { 1 << 16 } else { (p << 16) / (p + r) };
+                let x = x + (((z - x) * k as i64) >> 16);
+                let p = (((1 << 16) - k) * p) >> 16;
+                (x, p)
+            }
+            // First reading, nothing to filter against
+            None => (z, r),
+        };
+        self.state = Some((x, p));
+        Distance(((x + 128) >> 8).max(0) as u32)
+    }
+
+    fn reset(&mut self) {
+        self.state = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `readings` through `filter`, returning the last output
+    fn feed<F: Filter>(filter: &mut F, readings: &[u32]) -> u32 {
+        let mut out = 0;
+        for &mm in readings {
+            out = filter.update(Distance(mm)).mm();
+        }
+        out
+    }
+
+    #[test]
+    fn median_removes_outliers() {
+        let mut median = Median::<3>::new();
+        assert_eq!(feed(&mut median, &[1_000]), 1_000);
+        // Mean of the two middle readings until the window is full
+        assert_eq!(feed(&mut median, &[1_010]), 1_005);
+        assert_eq!(feed(&mut median, &[4_000]), 1_010);
+        assert_eq!(feed(&mut median, &[1_020]), 1_020);
+        assert_eq!(feed(&mut median, &[20, 1_030]), 1_020);
+        median.reset();
+        assert_eq!(feed(&mut median, &[500]), 500);
+    }
+
+    #[test]
+    fn moving_average() {
+        let mut average = MovingAverage::<4>::new();
+        assert_eq!(feed(&mut average, &[1_000]), 1_000);
+        assert_eq!(feed(&mut average, &[1_100]), 1_050);
+        assert_eq!(feed(&mut average, &[1_200, 1_300]), 1_150);
+        // The oldest reading drops out of the window
+        assert_eq!(feed(&mut average, &[1_400]), 1_250);
+        assert_eq!(feed(&mut average, &[u32::max_value(); 4]), u32::max_value());
+        average.reset();
+        assert_eq!(feed(&mut average, &[500]), 500);
+    }
+
+    #[test]
+    fn median_then_average() {
+        let mut filter = Median::<3>::new().chain(MovingAverage::<2>::new());
+        assert_eq!(feed(&mut filter, &[1_000]), 1_000);
+        assert_eq!(feed(&mut filter, &[1_020]), 1_005);
+        // The outlier is removed before it reaches the average
+        assert_eq!(feed(&mut filter, &[5_000]), 1_015);
+        assert_eq!(feed(&mut filter, &[1_040]), 1_030);
+        filter.reset();
+        assert_eq!(feed(&mut filter, &[300]), 300);
+    }
+
+    #[test]
+    fn weighted_average() {
+        let mut average = WeightedAverage::<2>::new();
+        assert_eq!(average.update_weighted(Distance(1_000), 255).mm(), 1_000);
+        assert_eq!(average.update_weighted(Distance(2_000), 85).mm(), 1_250);
+        // Plain mean without any confidence
+        average.reset();
+        average.update_weighted(Distance(1_000), 0);
+        assert_eq!(average.update_weighted(Distance(2_000), 0).mm(), 1_500);
+    }
+
+    #[test]
+    fn kalman_follows_steady_readings() {
+        let mut kalman = SimpleKalman::new(1, 9);
+        assert_eq!(feed(&mut kalman, &[1_000]), 1_000);
+        assert_eq!(feed(&mut kalman, &[1_000; 10]), 1_000);
+        // A step is smoothed but eventually followed
+        let first = feed(&mut kalman, &[1_100]);
+        assert!(first > 1_000 && first < 1_100);
+        assert_eq!(feed(&mut kalman, &[1_100; 100]), 1_100);
+        kalman.reset();
+        assert_eq!(feed(&mut kalman, &[500]), 500);
+    }
+
+    #[test]
+    fn dead_band_holds_output() {
+        let mut dead_band = DeadBand::new(5);
+        assert_eq!(feed(&mut dead_band, &[1_000]), 1_000);
+        assert_eq!(feed(&mut dead_band, &[1_005, 995, 1_003]), 1_000);
+        assert_eq!(feed(&mut dead_band, &[1_006]), 1_006);
+        assert_eq!(feed(&mut dead_band, &[1_002]), 1_006);
+        dead_band.reset();
+        assert_eq!(feed(&mut dead_band, &[1_002]), 1_002);
+    }
+
+    #[test]
+    fn reset_on_gap() {
+        let mut filter = ResetOnGap::new(MovingAverage::<4>::new(), 100);
+        assert_eq!(filter.update_at(Distance(1_000), 0).mm(), 1_000);
+        assert_eq!(filter.update_at(Distance(2_000), 100).mm(), 1_500);
+        // Gap too long, the average starts over
+        assert_eq!(filter.update_at(Distance(3_000), 201).mm(), 3_000);
+        assert_eq!(filter.update_at(Distance(1_000), u32::max_value() - 10).mm(), 1_000);
+        // A wrapping clock is not mistaken for a gap
+        assert_eq!(filter.update_at(Distance(2_000), 20).mm(), 1_500);
+    }
+}
@@ -0,0 +1,60 @@
+//! Clock and delay for running the driver on a desktop.
+//!
+//! Only available with the `std` feature. `StdClock` times the return pulse
+//! with `std::time::Instant` and `StdDelay` waits by sleeping the current
+//! thread, so that application code using the driver and its filters can be
+//! developed and tested off-target. Without a sensor attached the edges of
+//! the return pulse have to be simulated by calling `HcSr04::update`, see
+//! the `host` example.
+
+use std::thread;
+use std::time::{Duration, Instant};
+use hal::blocking::delay::DelayUs;
+use Clock;
+
+/// `Clock` counting microseconds since it was created.
+///
+/// The counter wraps after about 71 minutes, which the driver handles the
+/// same way as a wrapping hardware counter.
+pub struct StdClock {
+    /// Time the clock was created
+    start: Instant,
+}
+
+impl StdClock {
+    /// Create a new clock starting at zero.
+    pub fn new() -> Self {
+        StdClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for StdClock {
+    fn default() -> Self {
+        StdClock::new()
+    }
+}
+
+impl Clock for StdClock {
+    fn now(&self) -> u32 {
+        // Truncate so that the counter wraps like a hardware counter
+        self.start.elapsed().as_micros() as u32
+    }
+
+    fn frequency(&self) -> u32 {
+        1_000_000
+    }
+}
+
+/// Delay sleeping the current thread.
+///
+/// The operating system may sleep for longer than requested, which only
+/// stretches the trigger pulse and the settle delay of the driver.
+pub struct StdDelay;
+
+impl DelayUs<u32> for StdDelay {
+    fn delay_us(&mut self, us: u32) {
+        thread::sleep(Duration::from_micros(us as u64));
+    }
+}
@@ -7,78 +7,1027 @@
 //! us to accurately measure the pulse width, but at the cost of needing
 //! external support for calling `HcSr04::update`.
 //!
+//! To guard against lost interrupts the driver can be given a
+//! [`CountDown`][3] timer with `HcSr04::with_timeout`, alternatively call
+//! `HcSr04::timedout` from your own timeout handler.
+//!
 //! See the `examples` folder for further information.
 //!
+//! # Features
+//! The driver is `no_std`. With the `std` feature the `host` module provides
+//! a `Clock` and a delay based on the standard library, for running the
+//! driver and filters on a desktop, e.g. to test application code against
+//! simulated edges as in the `host` example.
+//!
 //! [1]: https://crates.io/crates/embedded-hal
 //! [2]: http://www.micropik.com/PDF/HCSR04.pdf
+//! [3]: https://docs.rs/embedded-hal/0.1.2/embedded_hal/timer/trait.CountDown.html
 
 #![deny(missing_docs)]
 #![deny(warnings)]
-#![feature(never_type)]
-#![no_std]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
+extern crate cortex_m;
 extern crate embedded_hal as hal;
+#[cfg(feature = "heapless")]
+extern crate heapless;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "uom")]
+extern crate uom;
 extern crate nb;
 extern crate stm32f30x_hal;
+extern crate void;
 
 /// Publicly re-export `nb::Error` for easier usage down-stream
 pub use nb::Error;
-use hal::digital::OutputPin;
+#[cfg(any(test, feature = "testing"))]
+use core::cell::Cell;
+use cortex_m::peripheral::DWT;
+use hal::digital::{InputPin, OutputPin};
 use hal::blocking::delay::DelayUs;
+use hal::timer::CountDown;
 use stm32f30x_hal::time::MonoTimer;
-use stm32f30x_hal::time::Instant;
+use stm32f30x_hal::time::Hertz;
+use filter::{Filter, NoFilter};
+use void::Void;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+pub mod builder;
+pub mod consts;
+pub mod filter;
+#[cfg(feature = "std")]
+pub mod host;
+#[cfg(test)]
+mod mock;
+pub mod schedule;
+pub mod stats;
+pub mod zone;
+
+/// Default speed factor used to convert the return pulse into distance.
+///
+/// The factor is half the speed of sound in air at 20°C (343.21 m/s) in
+/// millimeters per second, half since the pulse travels to the object and
+/// back again.
+pub const DEFAULT_SPEED_FACTOR: u32 = 171_605;
+
+/// Temperature in °C the driver assumes until `HcSr04::set_temperature` is
+/// called.
+///
+/// This is 20°C unless one of the `temperature-0c`, `temperature-10c`,
+/// `temperature-30c` or `temperature-40c` features is enabled, in which case
+/// the temperature of the feature is used. Features are additive, so several
+/// may end up enabled through different dependents, in that case the coldest
+/// of the enabled temperatures is used.
+#[cfg(not(any(
+    feature = "temperature-0c",
+    feature = "temperature-10c",
+    feature = "temperature-30c",
+    feature = "temperature-40c"
+)))]
+pub const DEFAULT_TEMPERATURE: i16 = 20;
+/// Temperature in °C the driver assumes until `HcSr04::set_temperature` is
+/// called, selected with the `temperature-0c` feature.
+#[cfg(feature = "temperature-0c")]
+pub const DEFAULT_TEMPERATURE: i16 = 0;
+/// Temperature in °C the driver assumes until `HcSr04::set_temperature` is
+/// called, selected with the `temperature-10c` feature.
+#[cfg(all(feature = "temperature-10c", not(feature = "temperature-0c")))]
+pub const DEFAULT_TEMPERATURE: i16 = 10;
+/// Temperature in °C the driver assumes until `HcSr04::set_temperature` is
+/// called, selected with the `temperature-30c` feature.
+#[cfg(all(
+    feature = "temperature-30c",
+    not(any(feature = "temperature-0c", feature = "temperature-10c"))
+))]
+pub const DEFAULT_TEMPERATURE: i16 = 30;
+/// Temperature in °C the driver assumes until `HcSr04::set_temperature` is
+/// called, selected with the `temperature-40c` feature.
+#[cfg(all(
+    feature = "temperature-40c",
+    not(any(
+        feature = "temperature-0c",
+        feature = "temperature-10c",
+        feature = "temperature-30c"
+    ))
+))]
+pub const DEFAULT_TEMPERATURE: i16 = 40;
+
+/// Coldest and warmest temperature in °C used for compensation
+const TEMPERATURE_RANGE: (i16, i16) = (-100, 200);
+
+/// Calculate the speed factor for air at the given temperature in °C.
+fn speed_factor_at(celsius: i16) -> u32 {
+    // Far outside of this range the linear approximation below is useless,
+    // and for extreme values it would turn negative
+    let celsius = celsius.max(TEMPERATURE_RANGE.0).min(TEMPERATURE_RANGE.1);
+    // The speed of sound in air increases by roughly 0.606 m/s per °C, i.e.
+    // 303 mm/s per °C for the halved speed factor. Adjust relative to the
+    // factor at 20°C so that the default is kept exactly. Signed math since
+    // the adjustment is negative below 20°C.
+    (DEFAULT_SPEED_FACTOR as i32 + 303 * (celsius as i32 - 20)) as u32
+}
+
+/// Speed factors precomputed for air from -40°C to 64°C in 5°C ranges.
+///
+/// Each entry gives the lowest temperature of its range and the speed factor
+/// at the middle of that range, computed from `331.3 * sqrt(1 + T / 273.15)`
+/// m/s. Within each range the factor is off by at most 2.5°C, roughly
+/// 1.5 m/s or 0.45%, in exchange for a lookup without any arithmetic. Use it
+/// with `HcSr04::set_speed_table`.
+pub const SPEED_FACTOR_TABLE: [(i16, u32); 21] = [
+    (-40, 153_860), (-35, 155_483), (-30, 157_090), (-25, 158_681),
+    (-20, 160_256), (-15, 161_815), (-10, 163_360), (-5, 164_890),
+    (0, 166_406), (5, 167_909), (10, 169_398), (15, 170_874),
+    (20, 172_337), (25, 173_789), (30, 175_228), (35, 176_655),
+    (40, 178_071), (45, 179_476), (50, 180_870), (55, 182_253),
+    (60, 183_626),
+];
+
+/// Look up the speed factor for `celsius` in a table sorted by temperature
+fn speed_factor_from_table(table: &[(i16, u32)], celsius: i16) -> Option<u32> {
+    // Temperatures below the first range use the first entry
+    let mut factor = table.first().map(|&(_, factor)| factor);
+    for &(lowest, entry) in table {
+        if lowest > celsius {
+            break;
+        }
+        factor = Some(entry);
+    }
+    factor
+}
+
+/// Cosine of 0° to 90° in steps of 1° scaled by 10 000
+const COS_TABLE: [u16; 91] = [
+    10000, 9998, 9994, 9986, 9976, 9962, 9945, 9925, 9903, 9877,
+    9848, 9816, 9781, 9744, 9703, 9659, 9613, 9563, 9511, 9455,
+    9397, 9336, 9272, 9205, 9135, 9063, 8988, 8910, 8829, 8746,
+    8660, 8572, 8480, 8387, 8290, 8192, 8090, 7986, 7880, 7771,
+    7660, 7547, 7431, 7314, 7193, 7071, 6947, 6820, 6691, 6561,
+    6428, 6293, 6157, 6018, 5878, 5736, 5592, 5446, 5299, 5150,
+    5000, 4848, 4695, 4540, 4384, 4226, 4067, 3907, 3746, 3584,
+    3420, 3256, 3090, 2924, 2756, 2588, 2419, 2250, 2079, 1908,
+    1736, 1564, 1392, 1219, 1045, 872, 698, 523, 349, 175,
+    0,
+];
+
+/// Narrow a value to `u32`, saturating instead of truncating
+fn saturate(value: u64) -> u32 {
+    if value > u32::max_value() as u64 {
+        u32::max_value()
+    } else {
+        value as u32
+    }
+}
+
+/// Millimeters per tick of a timer running at `hz` as Q32.32 for the speed
+/// factor `factor`, rounded up so that exact results are not truncated by one
+fn mm_per_tick_q32(factor: u32, hz: u32) -> u64 {
+    // 0 Hz is rejected wherever the frequency is configured, guard against it
+    // anyway rather than dividing by zero
+    let hz = hz.max(1) as u64;
+    (((factor as u64) << 32) + hz - 1) / hz
+}
+
+/// Ticks from `from` to `to` of a counter counting from `0` to `period - 1`,
+/// which may have wrapped once in between
+fn counter_span(from: u32, to: u32, period: u32) -> u32 {
+    if to >= from {
+        to - from
+    } else {
+        period - from + to
+    }
+}
+
+/// Convert `ticks` of a timer running at `hz` into millimeters with the
+/// constant from `mm_per_tick_q32`, the result is exactly `ticks * factor /
+/// hz` rounded down
+fn ticks_to_mm(ticks: u32, factor: u32, hz: u32, constant: u64) -> u64 {
+    let mm = (ticks as u64 * constant) >> 32;
+    // Rounding the constant up can overshoot by one for long pulses at high
+    // frequencies
+    if mm * hz as u64 > ticks as u64 * factor as u64 {
+        mm - 1
+    } else {
+        mm
+    }
+}
 
 /// Wrapper for return value of sensor
+///
+/// None of the conversions of `Distance` panic or overflow for any value,
+/// conversions which could exceed their range saturate instead.
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Distance(u32);
 
 impl Distance {
+    /// Create a distance from the width of the return pulse in
+    /// microseconds.
+    ///
+    /// This is useful when the pulse width is measured outside of the driver,
+    /// e.g. with an input capture peripheral.
+    ///
+    /// # Arguments
+    /// - `us` is the width of the return pulse in microseconds.
+    /// - `speed_factor` is half the speed of sound in millimeters per second,
+    /// use `DEFAULT_SPEED_FACTOR` for air at 20°C.
+    ///
+    /// The result saturates at `u32::MAX` millimeters.
+    pub fn from_echo_us(us: u32, speed_factor: u32) -> Distance {
+        Distance(saturate((us as u64 * speed_factor as u64) / 1_000_000))
+    }
+
+    /// Create a distance from the timestamps of both edges of a return pulse.
+    ///
+    /// This is useful when timing the return pulse outside of the driver,
+    /// e.g. with raw counter values from `Clock::now`, the cycle counter or
+    /// an input capture timer.
+    ///
+    /// # Arguments
+    /// - `start` and `end` are counter values of a timer running at `hz`,
+    /// taken at the rising and falling edge of the return pulse. The counter
+    /// may wrap once between the two, e.g. a 32-bit cycle counter.
+    /// `Instant`s from a `MonoTimer` don't expose their counter value, pass
+    /// `0` as `start` and the ticks from `Instant::elapsed`, taken at the
+    /// falling edge on the `Instant` of the rising edge, as `end` instead.
+    /// - `hz` is the frequency of the timer, a frequency of zero gives a
+    /// zero distance.
+    /// - `speed_factor` is the same as for `from_echo_us`.
+    ///
+    /// The result saturates at `u32::MAX` millimeters.
+    pub fn from_ticks(start: u32, end: u32, hz: u32, speed_factor: u32) -> Distance {
+        if hz == 0 {
+            return Distance(0);
+        }
+        let ticks = end.wrapping_sub(start) as u64;
+        Distance(saturate(ticks * speed_factor as u64 / hz as u64))
+    }
+
     /// Get distance as centimeters.
     pub fn cm(&self) -> u32 {
         self.0 / 10
     }
 
+    /// Get distance as signed centimeters.
+    ///
+    /// The same as `cm`, as a signed type for relative position code. Any
+    /// `Distance` fits, so this never overflows.
+    pub fn cm_i32(&self) -> i32 {
+        (self.0 / 10) as i32
+    }
+
+    /// Get the difference to `reference` in signed centimeters.
+    ///
+    /// The result is negative when this distance is closer than `reference`.
+    /// The difference is computed in millimeters and truncated towards zero,
+    /// see `error_from` for the difference in millimeters.
+    pub fn cm_relative_to(&self, reference: Distance) -> i32 {
+        ((self.0 as i64 - reference.0 as i64) / 10) as i32
+    }
+
     /// Get distance as millimeters.
     pub fn mm(&self) -> u32 {
         self.0
     }
+
+    /// Get distance as millimeters scaled to `decimals` decimal places.
+    ///
+    /// E.g. `scaled(1)` returns the distance in units of 0.1 mm and
+    /// `scaled(2)` in units of 0.01 mm. The result saturates at `u32::MAX`
+    /// instead of overflowing for large scales.
+    pub fn scaled(&self, decimals: u8) -> u32 {
+        (0..decimals).fold(self.0, |acc, _| acc.saturating_mul(10))
+    }
+
+    /// Get distance as tenths of an inch.
+    pub fn tenths_inch(&self) -> u32 {
+        // 1 inch is 25.4 mm, scale by 100 to get tenths of an inch with
+        // integer math
+        ((self.0 as u64 * 100) / 254) as u32
+    }
+
+    /// Get distance in the given `Unit`.
+    ///
+    /// The result is truncated to a whole number of the requested unit, the
+    /// same as the dedicated accessors.
+    pub fn in_unit(&self, unit: Unit) -> u32 {
+        match unit {
+            Unit::Mm => self.mm(),
+            Unit::Cm => self.cm(),
+            // 1 inch is 25.4 mm
+            Unit::Inch => ((self.0 as u64 * 10) / 254) as u32,
+            // 1 foot is 304.8 mm
+            Unit::Foot => ((self.0 as u64 * 10) / 3_048) as u32,
+        }
+    }
+
+    /// Get distance in all common units at once.
+    ///
+    /// The units are truncated the same as the dedicated accessors.
+    pub fn as_all(&self) -> Measurements {
+        Measurements {
+            mm: self.mm(),
+            cm: self.cm(),
+            inches: self.in_unit(Unit::Inch),
+        }
+    }
+
+    /// Get distance in the given `Unit` using the given `RoundingMode`.
+    pub fn in_unit_rounded(&self, unit: Unit, mode: RoundingMode) -> u32 {
+        // Express the conversion as a fraction of millimeters so that all
+        // units can share the same rounding logic
+        let (num, den) = match unit {
+            Unit::Mm => (self.0 as u64, 1),
+            Unit::Cm => (self.0 as u64, 10),
+            Unit::Inch => (self.0 as u64 * 10, 254),
+            Unit::Foot => (self.0 as u64 * 10, 3_048),
+        };
+        let (quot, rem) = (num / den, num % den);
+        let rounded = match mode {
+            RoundingMode::Truncate => quot,
+            RoundingMode::HalfUp if rem * 2 >= den => quot + 1,
+            RoundingMode::HalfEven if rem * 2 > den => quot + 1,
+            RoundingMode::HalfEven if rem * 2 == den => quot + (quot & 1),
+            _ => quot,
+        };
+        rounded as u32
+    }
+
+    /// Get distance as centimeters using the given `RoundingMode`.
+    pub fn cm_rounded(&self, mode: RoundingMode) -> u32 {
+        self.in_unit_rounded(Unit::Cm, mode)
+    }
+
+    /// Map distance onto a duty cycle, e.g. for a PWM or DAC output.
+    ///
+    /// Distances from `min_cm` to `max_cm` are mapped linearly onto
+    /// `0..=max_duty`, distances outside the range are clamped to the
+    /// nearest end.
+    pub fn to_duty(&self, min_cm: u32, max_cm: u32, max_duty: u16) -> u16 {
+        let min = min_cm as u64 * 10;
+        let max = max_cm as u64 * 10;
+        let mm = self.0 as u64;
+        if mm <= min {
+            0
+        } else if mm >= max {
+            max_duty
+        } else {
+            ((mm - min) * max_duty as u64 / (max - min)) as u16
+        }
+    }
+
+    /// Check if two distances are equal within `tolerance_mm` millimeters.
+    pub fn approx_eq(&self, other: Distance, tolerance_mm: u32) -> bool {
+        let diff = if self.0 > other.0 {
+            self.0 - other.0
+        } else {
+            other.0 - self.0
+        };
+        diff <= tolerance_mm
+    }
+
+    /// Get the deviation from `setpoint` in millimeters.
+    ///
+    /// The result is positive when this distance is further away than
+    /// `setpoint` and negative when it is closer, saturating at the limits
+    /// of `i32`.
+    pub fn error_from(&self, setpoint: Distance) -> i32 {
+        let diff = self.0 as i64 - setpoint.0 as i64;
+        diff.max(i32::min_value() as i64).min(i32::max_value() as i64) as i32
+    }
+
+    /// Get the ratio of this distance to `other` in parts per thousand.
+    ///
+    /// E.g. `1000` means the distances are equal and `500` that this
+    /// distance is half of `other`. Useful for comparing two sensors, e.g.
+    /// steering towards the more open side. The ratio saturates at
+    /// `u16::MAX`, which is also returned if `other` is zero and this
+    /// distance is not. Two zero distances are considered equal.
+    pub fn ratio_to(&self, other: Distance) -> u16 {
+        if other.0 == 0 {
+            return if self.0 == 0 { 1_000 } else { u16::max_value() };
+        }
+        (self.0 as u64 * 1_000 / other.0 as u64).min(u16::max_value() as u64) as u16
+    }
+
+    /// Project the distance onto the normal of the measured surface.
+    ///
+    /// When the sensor is mounted at an angle of `angle_deg` degrees to the
+    /// normal of the surface, the perpendicular distance to the surface is
+    /// `distance * cos(angle)`. The cosine is looked up in a table with a
+    /// resolution of 1° so no floating point math is needed. Angles above
+    /// 90° are treated as 90°.
+    pub fn project(&self, angle_deg: u8) -> Distance {
+        let cos = COS_TABLE[(angle_deg as usize).min(90)] as u64;
+        Distance(((self.0 as u64 * cos + 5_000) / 10_000) as u32)
+    }
+
+    /// Get distance as a percentage of the range `min_cm` to `max_cm`.
+    ///
+    /// Distances outside of the range are clamped to `0` or `100`.
+    pub fn percent_of_range(&self, min_cm: u32, max_cm: u32) -> u8 {
+        self.to_duty(min_cm, max_cm, 100) as u8
+    }
+
+    /// Pack the distance into 12 bits of whole centimeters.
+    ///
+    /// Distances of 4096 cm or more saturate at `0xFFF`, which covers the
+    /// full range of the sensor. The upper 4 bits are always zero.
+    pub fn to_packed12(&self) -> u16 {
+        self.cm().min(0xFFF) as u16
+    }
+
+    /// Unpack a distance packed with `to_packed12`.
+    ///
+    /// The upper 4 bits of `packed` are ignored.
+    pub fn from_packed12(packed: u16) -> Distance {
+        Distance((packed & 0xFFF) as u32 * 10)
+    }
+
+    /// Get distance as whole meters and remaining millimeters.
+    ///
+    /// Useful for displaying the distance as e.g. "1 m 234 mm".
+    pub fn meters_whole_and_mm(&self) -> (u32, u32) {
+        (self.0 / 1_000, self.0 % 1_000)
+    }
+
+    /// Get distance in meters as a value and a base 10 exponent.
+    ///
+    /// The distance is `value * 10^scale` meters, for telemetry formats
+    /// carrying such pairs. The scale is always `-3`, i.e. the value is the
+    /// distance in millimeters, which is the resolution of `Distance`, e.g.
+    /// 23.7 cm is encoded as `(237, -3)`.
+    pub fn value_and_scale(&self) -> (u32, i8) {
+        (self.0, -3)
+    }
+
+    /// Get distance as meters in Q16.16 fixed-point format.
+    ///
+    /// The upper 16 bits contain whole meters and the lower 16 bits the
+    /// fraction of a meter in units of `1 / 65536` m, i.e. the real value is
+    /// `meters_q16() as f32 / 65536.0`. Distances of 65 536 m or more, far
+    /// beyond the range of the sensor, saturate at `u32::MAX`.
+    pub fn meters_q16(&self) -> u32 {
+        saturate((self.0 as u64 * 65_536) / 1_000)
+    }
+}
+
+/// Convert into a `uom` length quantity, only available with the `uom`
+/// feature.
+///
+/// The quantity uses `f32` storage since `uom` stores quantities in the base
+/// unit of meters, integer storage would truncate the distance to whole
+/// meters.
+#[cfg(feature = "uom")]
+impl From<Distance> for uom::si::f32::Length {
+    fn from(distance: Distance) -> Self {
+        uom::si::f32::Length::new::<uom::si::length::millimeter>(distance.mm() as f32)
+    }
+}
+
+/// A distance in all common units, see `Distance::as_all`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Measurements {
+    /// Distance in millimeters
+    pub mm: u32,
+    /// Distance in whole centimeters
+    pub cm: u32,
+    /// Distance in whole inches
+    pub inches: u32,
+}
+
+/// Snapshot of the configuration of a driver, see `HcSr04::config`.
+///
+/// With the `serde` feature this can be serialized, e.g. to log the
+/// configuration alongside measurements.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Config {
+    /// Frequency in Hz of the timer measuring the return pulse
+    pub frequency_hz: u32,
+    /// Correction in nanoseconds subtracted from the width of the return
+    /// pulse, see `HcSr04::set_width_offset_ns`
+    pub width_offset_ns: u32,
+    /// Temperature set by the user in °C, if any
+    pub temperature: Option<i16>,
+    /// Width of the trigger pulse in microseconds
+    pub trigger_width_us: u32,
+    /// Shortest distance considered valid in millimeters
+    pub min_range_mm: u32,
+    /// Longest distance considered valid in millimeters
+    pub max_range_mm: u32,
+    /// Speed factor used for conversion, see `HcSr04::speed_factor`
+    pub speed_factor: u32,
+    /// Minimum time in microseconds between edges of the return pulse
+    pub debounce_us: u32,
+    /// Time in microseconds waited before each trigger pulse
+    pub settle_us: u32,
+    /// Shortest and longest valid return pulse in microseconds
+    pub pulse_limits_us: (u32, u32),
+    /// Number of measurements averaged for each reading
+    pub oversampling: u8,
+}
+
+/// Intermediate values of a conversion, see `HcSr04::last_computation`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Computation {
+    /// Width of the return pulse in ticks
+    pub ticks: u32,
+    /// Frequency in Hz of the timer the pulse was measured with
+    pub hz: u32,
+    /// Width of the return pulse in microseconds
+    pub echo_us: u32,
+    /// Ticks subtracted from the pulse, see `HcSr04::set_width_offset_ns`
+    pub offset_ticks: u32,
+    /// Speed factor applied, see `HcSr04::speed_factor`
+    pub speed_factor: u32,
+    /// Resulting distance in millimeters
+    pub distance_mm: u32,
+}
+
+/// Unit of length used with `Distance::in_unit`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Unit {
+    /// Millimeters
+    Mm,
+    /// Centimeters
+    Cm,
+    /// Inches
+    Inch,
+    /// Feet
+    Foot,
+}
+
+/// Rounding used when converting a `Distance` into a coarser unit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Round towards zero, this is what the plain accessors do
+    Truncate,
+    /// Round to nearest, ties away from zero
+    HalfUp,
+    /// Round to nearest, ties to even
+    HalfEven,
+}
+
+impl Default for RoundingMode {
+    fn default() -> Self {
+        RoundingMode::Truncate
+    }
 }
 
 /// Possible error returned by sensor.
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum SensorError {
     /// Sensor is in wrong mode for update to take place.
     WrongMode,
+    /// Echo pin stayed high for longer than the sensor can produce, most
+    /// likely the echo line is shorted high or the falling edge interrupt is
+    /// not configured.
+    EchoStuck {
+        /// Ticks since the start of the return pulse
+        ticks: u32,
+    },
+    /// Return pulse was too short to be real, longer than the sensor ever
+    /// produces or longer than possible for the configured maximum distance,
+    /// see `HcSr04::set_max_distance`.
+    ImplausiblePulse {
+        /// Width of the return pulse in ticks
+        ticks: u32,
+    },
+    /// Return pulse was outside of the limits for a valid reading, see
+    /// `HcSr04::set_pulse_limits`.
+    OutOfSpec {
+        /// Width of the return pulse in ticks
+        ticks: u32,
+    },
+    /// No object was detected within range of the sensor.
+    ///
+    /// This is not a fault, the sensor signals that nothing reflected the
+    /// pulse by producing the longest possible return pulse.
+    NoObjectDetected {
+        /// Width of the return pulse in ticks
+        ticks: u32,
+    },
+    /// The sensor is not allowed to measure, see `HcSr04::inhibit`.
+    Inhibited,
+    /// The reading was rejected by the function set with
+    /// `HcSr04::set_reading_filter`.
+    Rejected {
+        /// Width of the return pulse in ticks
+        ticks: u32,
+    },
+    /// A captured timestamp was not within the period of the counter, see
+    /// `HcSr04::set_counter_period`.
+    InvalidTimestamp {
+        /// The offending timestamp
+        timestamp: u32,
+    },
+}
+
+/// Outcome of `HcSr04::start_measurement`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MeasurementStart {
+    /// Sensor was triggered and a new measurement started
+    Started,
+    /// A measurement is in progress, or its result has not yet been read
+    /// with `distance`, the sensor was not triggered
+    AlreadyBusy,
+    /// The sensor is inhibited, see `HcSr04::inhibit`, the sensor was not
+    /// triggered
+    Inhibited,
+}
+
+/// Variants of the sensor supported by the driver, see
+/// `HcSr04::set_variant`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SensorVariant {
+    /// The original 5 V HC-SR04
+    HcSr04,
+    /// The HC-SR04P, which runs from 3.3 V as well as 5 V.
+    ///
+    /// Common on ESP32 and other 3.3 V boards. The timing and range are the
+    /// same as the original, so the driver treats both the same. At 3.3 V
+    /// the echo pin is driven at 3.3 V and can be connected directly, unlike
+    /// the 5 V echo of the original which needs a level shifter or a 5 V
+    /// tolerant pin.
+    HcSr04P,
 }
 
+impl SensorVariant {
+    /// Shortest and longest valid return pulse in microseconds of this
+    /// variant, see `HcSr04::set_pulse_limits`.
+    pub fn pulse_limits_us(&self) -> (u32, u32) {
+        match *self {
+            SensorVariant::HcSr04 | SensorVariant::HcSr04P => {
+                (consts::MIN_VALID_ECHO_US, consts::MAX_VALID_ECHO_US)
+            }
+        }
+    }
+}
+
+/// Outcome of `HcSr04::timing_health`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimingHealth {
+    /// No oversampled reading has completed yet
+    Unknown,
+    /// The pulse widths varied within the expected bound
+    Stable,
+    /// The pulse widths varied more than expected
+    Unstable,
+}
+
+/// Time in milliseconds a return pulse can last before the echo line is
+/// considered stuck high.
+///
+/// The sensor ends the pulse after roughly 38 ms when no object is detected,
+/// this leaves some margin above that.
+const ECHO_STUCK_MS: u32 = 60;
+
+/// Return pulses at least this long, in microseconds, mean that no object
+/// was detected.
+///
+/// The sensor produces a return pulse of roughly 38 ms (see
+/// `consts::MAX_ECHO_US`) when nothing reflects the pulse, while an object at
+/// the maximum range of 4 m produces a pulse of about 23 ms. The threshold is
+/// placed between the two since the exact length varies between modules.
+const NO_OBJECT_US: u32 = 30_000;
+
+/// Default minimum time in microseconds between the edges of the return
+/// pulse, slightly shorter than the pulse produced at the minimum range of
+/// 2 cm.
+const DEFAULT_DEBOUNCE_US: u32 = 100;
+
 /// Sensor Mode
+#[derive(Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 enum Mode {
     /// Ready to start new measurement
     Idle,
     /// Sensor has been triggered, waiting for return
     Triggered,
     /// Input pin pulled high
-    MeasurePulse(Instant),
+    MeasurePulse(u32),
+    /// Input pin pulled high, timestamp captured by external timer
+    CapturePulse(u16),
     /// Measurement is ready
     Measurement(Distance),
+    /// Measurement failed
+    Failed(SensorError),
+}
+
+/// Opaque snapshot of the internal state of the driver.
+///
+/// Created by `HcSr04::debug_snapshot` and restored with
+/// `HcSr04::debug_restore`, only available with the `testing` feature. The
+/// snapshot holds the state of the measurement in progress and of the last
+/// completed one, not the configuration of the driver. With the `serde`
+/// feature it can be serialized, e.g. to replay a recorded state.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Snapshot {
+    /// Internal mode of sensor, including any captured timestamp
+    mode: Mode,
+    /// Time the last trigger pulse was sent
+    triggered_at: Option<u32>,
+    /// Time the last measurement cycle started
+    cycle_start: Option<u32>,
+    /// Time of the last edge seen by `update`
+    last_edge: u32,
+    /// An odd number of edges has been ignored since the last accepted edge
+    odd_ignored_edges: bool,
+    /// Consecutive samples seen of the pending edge in `poll_echo`
+    poll_count: u8,
+    /// Number of measurements taken of the current reading
+    burst_len: u8,
+    /// Sum in millimeters of the measurements of the current reading
+    burst_sum: u64,
+    /// Sum and sum of squares of the pulse widths of the current reading
+    burst_ticks: (u64, u64),
+    /// Width in ticks of the last completed return pulse and the frequency
+    /// of the timer it was measured with
+    last_ticks: Option<(u32, u32)>,
+    /// Outcome of the last measurement
+    last_result: Option<Result<Distance, SensorError>>,
+    /// Last reading returned from `distance`
+    last_reading: Option<Distance>,
+    /// An edge was seen after the return pulse of the last measurement
+    multiple_echoes: bool,
+    /// The falling edge of the last return pulse was seen
+    pulse_ended: bool,
+}
+
+/// Generic interface to read one value at a time from a sensor.
+///
+/// This mirrors the semantics of the `OneShot` ADC trait of `embedded-hal`
+/// so that the sensor can be used by code reading many different sensors
+/// generically. For `HcSr04` calling `read` is the same as calling
+/// `HcSr04::distance`, the first call starts a measurement and returns
+/// `WouldBlock` until the result is ready.
+pub trait OneShot {
+    /// Type of value read
+    type Word;
+    /// Type of error returned
+    type Error;
+
+    /// Request a value, returning `WouldBlock` until it is ready.
+    fn read(&mut self) -> nb::Result<Self::Word, Self::Error>;
+}
+
+/// Placeholder `CountDown` used when no timeout timer is given.
+///
+/// This timer never expires, which means that the driver will wait
+/// indefinitely for the sensor unless `HcSr04::timedout` is called.
+pub struct NoTimeout;
+
+impl CountDown for NoTimeout {
+    type Time = ();
+
+    fn start<T>(&mut self, _count: T)
+    where
+        T: Into<()>,
+    {
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        Err(Error::WouldBlock)
+    }
+}
+
+/// Free running counter used to time the return pulse.
+///
+/// The driver only needs the current count and the rate it increments at,
+/// the time between two events is the wrapping difference of their counts.
+/// The counter may wrap at 32 bits, but must not wrap more than once during
+/// a measurement, i.e. at most 60 ms. This holds for any frequency up to
+/// roughly 70 GHz.
+///
+/// A frequency of 0 Hz is rejected by the builder. The driver never divides
+/// by it, so a clock reporting 0 Hz, e.g. a mock which is not set up yet,
+/// gives meaningless timings rather than a panic.
+pub trait Clock {
+    /// Current value of the counter
+    fn now(&self) -> u32;
+    /// Rate in Hz at which the counter increments
+    fn frequency(&self) -> u32;
+}
+
+impl Clock for MonoTimer {
+    fn now(&self) -> u32 {
+        // `MonoTimer` is backed by the cycle counter, but its `Instant` does
+        // not expose the raw count
+        DWT::get_cycle_count()
+    }
+
+    fn frequency(&self) -> u32 {
+        MonoTimer::frequency(self).0
+    }
+}
+
+impl<'a, C> Clock for &'a C
+where
+    C: Clock,
+{
+    fn now(&self) -> u32 {
+        (**self).now()
+    }
+
+    fn frequency(&self) -> u32 {
+        (**self).frequency()
+    }
+}
+
+/// `Clock` which only moves when told to.
+///
+/// This makes the timing of the driver deterministic, e.g. for tests on the
+/// host. Give the driver a reference to keep control of the clock:
+///
+/// ```ignore
+/// let clock = MockClock::new(1_000_000);
+/// let mut sensor = HcSr04::new(trigger, delay, &clock);
+/// sensor.fire()?;
+/// sensor.update()?;
+/// clock.advance(580);
+/// sensor.update()?;
+/// ```
+///
+/// Only available with the `testing` feature.
+#[cfg(any(test, feature = "testing"))]
+pub struct MockClock {
+    /// Current value of the counter
+    now: Cell<u32>,
+    /// Rate in Hz the counter pretends to increment at
+    hz: u32,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl MockClock {
+    /// Create a clock running at `hz`, starting at zero.
+    pub fn new(hz: u32) -> Self {
+        MockClock {
+            now: Cell::new(0),
+            hz: hz,
+        }
+    }
+
+    /// Move the counter forward by `ticks`, wrapping at 32 bits.
+    pub fn advance(&self, ticks: u32) {
+        self.now.set(self.now.get().wrapping_add(ticks));
+    }
+
+    /// Set the counter to `now`.
+    pub fn set(&self, now: u32) {
+        self.now.set(now);
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Clock for MockClock {
+    fn now(&self) -> u32 {
+        self.now.get()
+    }
+
+    fn frequency(&self) -> u32 {
+        self.hz
+    }
 }
 
+/// Placeholder used when the driver does not own the echo pin.
+///
+/// The echo pin can be handed to the driver with
+/// `HcSr04Builder::echo`, see `HcSr04::update_echo` for the methods this
+/// makes available. Without it the pin is passed to the methods reading it,
+/// e.g. `HcSr04::update_from_pin`, or not read by the driver at all.
+pub struct NoEcho;
+
 /// HC-SR04 device
-pub struct HcSr04<Pin, Delay> {
+pub struct HcSr04<
+    Pin,
+    Delay,
+    Timeout = NoTimeout,
+    Clk = MonoTimer,
+    EchoPin = NoEcho,
+    Filt = NoFilter,
+> where
+    Timeout: CountDown,
+{
     /// Output pin to trigger sensor
     pin: Pin,
+    /// Input pin connected to the echo of the sensor, only taken out while
+    /// it is being read
+    echo: Option<EchoPin>,
     /// Delay to wait on for sensor trigger
     delay: Delay,
     /// Timer to estimate returning pulse width
-    timer: MonoTimer,
+    timer: Clk,
+    /// Timer used to abort measurements which never complete
+    timeout: Timeout,
+    /// Period the timeout timer is started with on trigger
+    timeout_period: Timeout::Time,
+    /// Minimum time in microseconds between edges of the return pulse
+    debounce_us: u32,
+    /// Time of the last edge seen by `update`, accepted or ignored, or the
+    /// counter value of the last edge given to `capture_with_period`
+    last_edge: u32,
+    /// An odd number of edges has been ignored since the last accepted edge
+    odd_ignored_edges: bool,
+    /// Consecutive samples needed to accept an edge in `poll_echo`
+    poll_threshold: u8,
+    /// Consecutive samples seen of the pending edge in `poll_echo`
+    poll_count: u8,
+    /// Time in microseconds to wait before sending the trigger pulse
+    settle_us: u32,
+    /// Half the speed of sound in millimeters per second
+    speed_factor: u32,
+    /// Temperature set by the user in °C
+    temperature: Option<i16>,
+    /// Table of precomputed speed factors used by `set_temperature`
+    speed_table: Option<&'static [(i16, u32)]>,
+    /// Scale the minimum range with the speed factor
+    scale_min_range: bool,
+    /// Pulse travels one way rather than there and back
+    one_way: bool,
+    /// Speed factor and timer frequency the conversion constant was last
+    /// computed for, and the constant in millimeters per tick as Q32.32
+    conversion: Option<(u32, u32, u64)>,
+    /// Function called with each successful measurement
+    on_complete: Option<fn(Distance)>,
+    /// Function deciding if a measured distance is acceptable
+    reading_filter: Option<fn(Distance) -> bool>,
+    /// Function reading the current temperature in °C
+    temperature_fn: Option<fn() -> i16>,
+    /// Frequency of the external timer used for captured timestamps
+    capture_hz: Option<u32>,
+    /// Period of the counter used with `capture_pair` and `capture_with_period`
+    counter_period: Option<u32>,
+    /// Correction in nanoseconds subtracted from the width of the return pulse
+    width_offset_ns: u32,
+    /// Variant of the sensor connected
+    variant: SensorVariant,
+    /// Shortest and longest valid return pulse in microseconds
+    pulse_limits: (u32, u32),
+    /// Largest distance considered plausible
+    max_distance: Option<Distance>,
+    /// Width in ticks of the last completed return pulse and the frequency
+    /// of the timer it was measured with
+    last_ticks: Option<(u32, u32)>,
+    /// Intermediate values of the last conversion
+    last_computation: Option<Computation>,
+    /// Time the last trigger pulse was sent
+    triggered_at: Option<u32>,
+    /// Time in microseconds from trigger to end of the last return pulse
+    last_latency_us: Option<u32>,
+    /// Time the last measurement cycle started, kept after it completes
+    cycle_start: Option<u32>,
+    /// Total time in ticks spent measuring
+    active_ticks: u64,
+    /// Shortest and longest trigger pulse in ticks
+    trigger_widths: Option<(u32, u32)>,
+    /// Outcome of the last measurement
+    last_result: Option<Result<Distance, SensorError>>,
+    /// Last reading returned from `distance`
+    last_reading: Option<Distance>,
+    /// Time of the `Clock` at which `last_reading` was returned
+    last_reading_at: Option<u32>,
+    /// Return `last_reading` instead of blocking while busy
+    return_last_while_busy: bool,
+    /// Only trigger the sensor on explicit request, never from `distance`
+    manual_trigger: bool,
+    /// Refuse to start new measurements
+    inhibited: bool,
+    /// Number of measurements averaged for each reading
+    oversampling: u8,
+    /// Number of measurements taken of the current reading
+    burst_len: u8,
+    /// Sum in millimeters of the measurements of the current reading
+    burst_sum: u64,
+    /// Sum and sum of squares of the pulse widths in ticks of the current
+    /// reading
+    burst_ticks: (u64, u64),
+    /// Variance of the pulse widths of the last oversampled reading and the
+    /// frequency of the timer they were measured with
+    pulse_variance: Option<(u64, u32)>,
+    /// Treat edges after the return pulse as further echoes
+    detect_multiple_echoes: bool,
+    /// An edge was seen after the return pulse of the last measurement
+    multiple_echoes: bool,
+    /// The falling edge of the last return pulse was seen, later edges are
+    /// further echoes
+    pulse_ended: bool,
+    /// Filter applied to the readings returned from `distance`
+    filter: Filt,
     /// Internal mode of sensor
     mode: Mode,
 }
 
-impl<Pin, Delay> HcSr04<Pin, Delay>
+impl<Pin, Delay, Clk> HcSr04<Pin, Delay, NoTimeout, Clk>
 where
     Pin: OutputPin,
     Delay: DelayUs<u32>,
+    Clk: Clock,
 {
     /// Create a new driver.
     ///
@@ -87,8 +1036,68 @@ where
     /// the sensor into taking a measurement.
     /// - `delay` is a timer used to wait for the sensor to trigger.
     /// - `timer` is a timer used to estimate the pulse width of the sensor
-    /// return.
-    pub fn new(trigger: Pin, delay: Delay, timer: MonoTimer) -> Self {
+    /// return, usually the `MonoTimer` of the HAL, see `Clock`.
+    pub fn new(trigger: Pin, delay: Delay, timer: Clk) -> Self {
+        HcSr04::with_timeout(trigger, delay, timer, NoTimeout, ())
+    }
+}
+
+impl<Pin, Delay, Timeout, Clk> HcSr04<Pin, Delay, Timeout, Clk>
+where
+    Pin: OutputPin,
+    Delay: DelayUs<u32>,
+    Timeout: CountDown,
+    Timeout::Time: Copy,
+    Clk: Clock,
+{
+    /// Create a new driver which aborts measurements after a timeout.
+    ///
+    /// The `timeout` timer is started with `period` every time the sensor is
+    /// triggered and checked each time `distance` is polled. If it expires
+    /// before the measurement is complete the driver calls `timedout` and the
+    /// next poll will trigger a new measurement.
+    ///
+    /// # Arguments
+    /// - `trigger`, `delay` and `timer` are the same as for `HcSr04::new`.
+    /// - `timeout` is a `CountDown` timer which is owned by the driver.
+    /// - `period` is the time to wait before abandoning a measurement.
+    ///
+    /// # Note
+    /// The sensor itself will keep the echo pin high for up to 38 ms if no
+    /// object is detected, the timeout timer should therefore have a
+    /// resolution good enough to reliably represent a period longer than
+    /// this (e.g. 50 ms). A too short period will abort valid measurements.
+    pub fn with_timeout(
+        trigger: Pin,
+        delay: Delay,
+        timer: Clk,
+        timeout: Timeout,
+        period: Timeout::Time,
+    ) -> Self {
+        HcSr04::from_parts(trigger, delay, timer, timeout, period, NoEcho, NoFilter)
+    }
+}
+
+impl<Pin, Delay, Timeout, Clk, EchoPin, Filt> HcSr04<Pin, Delay, Timeout, Clk, EchoPin, Filt>
+where
+    Pin: OutputPin,
+    Delay: DelayUs<u32>,
+    Timeout: CountDown,
+    Timeout::Time: Copy,
+    Clk: Clock,
+    Filt: Filter,
+{
+    /// Create a driver from all of its parts, see `with_timeout` and
+    /// `HcSr04Builder`
+    fn from_parts(
+        trigger: Pin,
+        delay: Delay,
+        timer: Clk,
+        timeout: Timeout,
+        period: Timeout::Time,
+        echo: EchoPin,
+        filter: Filt,
+    ) -> Self {
         // Ensure that our starting state is valid, if the pin was already
         // high then all internal methods would have to account for that
         // possibility, by defensively setting it low all internal states
@@ -97,12 +1106,108 @@ where
         trigger.set_low();
         HcSr04 {
             pin: trigger,
+            echo: Some(echo),
             delay: delay,
             timer: timer,
+            timeout: timeout,
+            timeout_period: period,
+            debounce_us: DEFAULT_DEBOUNCE_US,
+            last_edge: 0,
+            odd_ignored_edges: false,
+            poll_threshold: 1,
+            poll_count: 0,
+            settle_us: 0,
+            speed_factor: speed_factor_at(DEFAULT_TEMPERATURE),
+            temperature: None,
+            speed_table: None,
+            scale_min_range: false,
+            one_way: false,
+            conversion: None,
+            on_complete: None,
+            reading_filter: None,
+            temperature_fn: None,
+            capture_hz: None,
+            counter_period: None,
+            width_offset_ns: 0,
+            variant: SensorVariant::HcSr04,
+            pulse_limits: SensorVariant::HcSr04.pulse_limits_us(),
+            max_distance: None,
+            last_ticks: None,
+            last_computation: None,
+            triggered_at: None,
+            last_latency_us: None,
+            cycle_start: None,
+            active_ticks: 0,
+            trigger_widths: None,
+            last_result: None,
+            last_reading: None,
+            last_reading_at: None,
+            return_last_while_busy: false,
+            manual_trigger: false,
+            inhibited: false,
+            oversampling: 1,
+            burst_len: 0,
+            burst_sum: 0,
+            burst_ticks: (0, 0),
+            pulse_variance: None,
+            detect_multiple_echoes: false,
+            multiple_echoes: false,
+            pulse_ended: false,
+            filter: filter,
             mode: Mode::Idle,
         }
     }
 
+    /// Read the temperature with `read_temp` before each conversion.
+    ///
+    /// `read_temp` should return the temperature of the air in °C, e.g. from
+    /// an on-chip temperature sensor. It is called once for every completed
+    /// return pulse, right before the pulse is converted into a distance, and
+    /// the result is applied as with `set_temperature`.
+    ///
+    /// # Note
+    /// The conversion happens in `update`, `read_temp` will therefore
+    /// normally run in interrupt context and should be quick.
+    pub fn with_temperature_fn(mut self, read_temp: fn() -> i16) -> Self {
+        self.temperature_fn = Some(read_temp);
+        self
+    }
+
+    /// Set a function to call as soon as a measurement completes.
+    ///
+    /// `on_complete` is called with the distance of each successful
+    /// measurement, allowing fully interrupt driven designs to react without
+    /// polling `distance`. The result is still available from `distance` as
+    /// usual. With oversampling (see `set_oversampling`) it is called for
+    /// every measurement, not the averaged reading. Pass `None` to remove
+    /// the function.
+    ///
+    /// # Note
+    /// The function is called from `update` and the other capture methods,
+    /// so it normally runs in interrupt context and should be quick.
+    pub fn set_on_complete(&mut self, on_complete: Option<fn(Distance)>) {
+        self.on_complete = on_complete;
+    }
+
+    /// Set a function deciding whether a measured distance is acceptable.
+    ///
+    /// This allows application specific plausibility checks on top of the
+    /// checks of the driver, e.g. rejecting distances which are impossible
+    /// for a known mounting. `accept` is called with the distance of every
+    /// measurement which passed the checks of the driver, if it returns
+    /// `false` the measurement fails with `SensorError::Rejected` the same
+    /// way as any other error. With oversampling (see `set_oversampling`)
+    /// it is called for every measurement, not the averaged reading. Pass
+    /// `None` to remove the function.
+    ///
+    /// # Note
+    /// As with `set_on_complete` the function is called from `update` and
+    /// the other capture methods, so it normally runs in interrupt context
+    /// and should be quick.
+    pub fn set_reading_filter(&mut self, accept: Option<fn(Distance) -> bool>) {
+        self.reading_filter = accept;
+    }
+
     /// Trigger sensor reading and return the resulting `Distance`.
     ///
     /// This function uses [`nb::Error::WouldBlock`][1] to signal that a
@@ -110,65 +1215,3097 @@ where
     /// user responsibility of calling `update` on interrupt, the function
     /// will return the distance.
     ///
-    /// # Note
-    /// This method will not return another error except [`WouldBlock`][1].
+    /// # Errors
+    /// If the return pulse has lasted for more than 60 ms this method
+    /// returns `SensorError::EchoStuck` and the sensor is reset so that the
+    /// next call triggers a new measurement. Errors detected during `update`
+    /// are returned here in the same way. Where available the errors carry
+    /// the raw width of the return pulse in timer ticks to aid debugging.
+    ///
+    /// A return pulse of 30 to 60 ms is reported as
+    /// `SensorError::NoObjectDetected`, meaning that nothing is within range
+    /// of the sensor. This should be treated as a clear path rather than a
+    /// fault. Longer pulses can't come from the sensor and are reported as
+    /// `SensorError::ImplausiblePulse`.
+    ///
+    /// # Filtering
+    /// Readings are passed through the filter given to
+    /// `HcSr04Builder::filter`, if any, before they are returned, errors
+    /// bypass the filter. The unfiltered measurements remain available from
+    /// `peek_distance` and `set_on_complete`, see `filter_mut` to e.g. reset
+    /// the filter.
+    ///
+    /// Each reading after the first is passed with the time since the
+    /// previous reading, measured with the `Clock`, see
+    /// `Filter::update_after`. A gap longer than the time the `Clock` takes
+    /// to wrap around, e.g. about 60 s for the cycle counter at 72 MHz, may
+    /// be taken for a short one.
     ///
     /// [1]: https://docs.rs/nb/0.1.1/nb/enum.Error.html
-    pub fn distance(&mut self) -> nb::Result<Distance, !> {
+    pub fn distance(&mut self) -> nb::Result<Distance, SensorError> {
         match self.mode {
+            // Not allowed to start a new measurement
+            Mode::Idle if self.inhibited => Err(Error::Other(SensorError::Inhibited)),
+            // Waiting for an explicit trigger
+            Mode::Idle if self.manual_trigger => self.busy(),
             // Start a new sensor measurement
             Mode::Idle => {
                 self.trigger();
-                Err(Error::WouldBlock)
+                self.busy()
+            }
+            // The return pulse has lasted far longer than the sensor is
+            // able to produce, the falling edge is never coming
+            Mode::MeasurePulse(start)
+                if self.elapsed(start) as u64
+                    > self.timer.frequency() as u64 * ECHO_STUCK_MS as u64 / 1_000 =>
+            {
+                let err = SensorError::EchoStuck {
+                    ticks: self.elapsed(start),
+                };
+                self.timedout();
+                self.last_result = Some(Err(err));
+                Err(Error::Other(err))
             }
             // We have triggered the sensor and are awaiting start of
-            // return pulse
-            Mode::Triggered => Err(Error::WouldBlock),
-            // We have detected start of return pulse, wait for end of pulse
-            Mode::MeasurePulse(_) => Err(Error::WouldBlock),
+            // return pulse, or we have detected start of return pulse and
+            // are waiting for the end of the pulse
+            Mode::Triggered | Mode::MeasurePulse(_) | Mode::CapturePulse(_) => {
+                // If the timeout timer has run out we give up on the
+                // current measurement, the next poll will start over
+                if self.timeout.wait().is_ok() {
+                    self.timedout();
+                }
+                self.busy()
+            }
             // End of pulse detected and distance is ready
             Mode::Measurement(dist) => {
                 self.mode = Mode::Idle;
-                Ok(dist)
+                let reading = if self.oversampling <= 1 {
+                    dist
+                } else {
+                    self.burst_sum += dist.mm() as u64;
+                    self.burst_len += 1;
+                    let (ticks, hz) = self.last_ticks.unwrap_or((0, 0));
+                    let ticks = ticks as u64;
+                    self.burst_ticks.0 += ticks;
+                    self.burst_ticks.1 += ticks * ticks;
+                    // Keep measuring until the burst is complete
+                    if self.burst_len < self.oversampling {
+                        // The rest of the burst can't be measured
+                        if self.inhibited {
+                            self.discard_burst();
+                            return Err(Error::Other(SensorError::Inhibited));
+                        }
+                        self.trigger();
+                        return self.busy();
+                    }
+                    let avg = self.burst_sum / self.burst_len as u64;
+                    // Variance as the mean of squares minus the squared mean
+                    let n = self.burst_len as u64;
+                    let (sum, sum_sq) = self.burst_ticks;
+                    self.pulse_variance = Some(((sum_sq - sum * sum / n) / n, hz));
+                    self.discard_burst();
+                    Distance(avg as u32)
+                };
+                // Let the filter know how long ago the previous reading was
+                let now = self.timer.now();
+                let reading = match self.last_reading_at {
+                    Some(last) => {
+                        let hz = self.timer.frequency().max(1) as u64;
+                        let elapsed_ms = now.wrapping_sub(last) as u64 * 1_000 / hz;
+                        self.filter.update_after(reading, elapsed_ms as u32)
+                    }
+                    None => self.filter.update(reading),
+                };
+                self.last_reading = Some(reading);
+                self.last_reading_at = Some(now);
+                Ok(reading)
+            }
+            // Measurement failed, report error and start over on next poll
+            Mode::Failed(err) => {
+                self.mode = Mode::Idle;
+                // A failure discards the whole burst
+                self.discard_burst();
+                Err(Error::Other(err))
             }
         }
     }
 
-    /// Update the internal state noting that an external interrupt has
-    /// occurred.
+    /// Poll `distance` and check if the sensor may be triggered again.
     ///
-    /// This function updates the internal state in response to an external
-    /// interrupt caused by the sensor. This interface will be removed once
-    /// abstract interrupt handling is supported.
+    /// The first element is the result of `distance`. The second is the
+    /// result of `cooldown_elapsed` after polling, i.e. `true` if triggering
+    /// a new measurement right now is safe. A loop can use it to wait for
+    /// the end of the measurement cycle before polling again, rather than
+    /// keeping track of the time itself.
+    pub fn distance_with_ready(&mut self) -> (nb::Result<Distance, SensorError>, bool) {
+        let result = self.distance();
+        (result, self.cooldown_elapsed())
+    }
+
+    /// Check if the recommended time has passed since the last trigger.
     ///
-    /// # Return
-    /// This function will return `Result::Ok` if called in the correct
-    /// state. Otherwise it will return `Result::Err`.
-    pub fn update(&mut self) -> Result<(), SensorError> {
-        self.mode = match self.mode {
-            Mode::Triggered => Mode::MeasurePulse(self.timer.now()),
-            Mode::MeasurePulse(ref start) => {
-                // How many ticks have passed since we started measurement
-                let ticks = start.elapsed();
-                // What does these ticks mean?
-                let hz = self.timer.frequency().0;
-                // Calculation is `distance = seconds * 343.21 m/s * 0.5`
-                // By doing some pre-calculations we can simply perform
-                // the following to get millimeters:
-                let distance_mm = (ticks * 171_605) / hz;
-                // Update internal mode
-                Mode::Measurement(Distance(distance_mm))
+    /// Triggering the sensor sooner than `consts::MEASUREMENT_CYCLE_US`
+    /// after the previous trigger can pick up stray echoes of the previous
+    /// measurement. This returns `true` if the sensor has never been
+    /// triggered or the full cycle has passed, as measured by the
+    /// `Clock`.
+    ///
+    /// # Note
+    /// The `Clock` wraps around, e.g. the `MonoTimer` after roughly a minute
+    /// at 72 MHz, so after a long pause this may briefly report `false` again.
+    pub fn cooldown_elapsed(&self) -> bool {
+        match self.cycle_start {
+            Some(start) => {
+                let hz = self.timer.frequency() as u64;
+                self.elapsed(start) as u64 >= consts::MEASUREMENT_CYCLE_US as u64 * hz / 1_000_000
             }
-            _ => return Err(SensorError::WrongMode),
-        };
-        Ok(())
+            None => true,
+        }
     }
 
-    /// Trigger sensor starting a measurement
-    fn trigger(&mut self) {
-        self.pin.set_high();
-        self.delay.delay_us(10);
-        self.pin.set_low();
-        self.mode = Mode::Triggered;
+    /// Choose whether `distance` returns the previous reading while busy.
+    ///
+    /// By default `distance` returns `WouldBlock` while a measurement is in
+    /// progress. When enabled it instead returns the last reading it
+    /// returned, if any, which gives a smooth display without waiting for
+    /// each measurement. The returned reading is at least one measurement
+    /// old, and arbitrarily old if measurements keep failing, so this should
+    /// not be enabled where fresh readings matter. Errors are still returned
+    /// as they occur.
+    pub fn set_return_last_while_busy(&mut self, enable: bool) {
+        self.return_last_while_busy = enable;
+    }
+
+    /// Choose whether `distance` may trigger the sensor.
+    ///
+    /// By default polling `distance` while the sensor is idle triggers a new
+    /// measurement. When manual triggering is enabled `distance` instead
+    /// returns `WouldBlock` while idle, or the last reading if
+    /// `set_return_last_while_busy` is enabled, and measurements are only
+    /// started by `start_measurement`, `fire` or `restart`. Polling
+    /// `distance` after a completed measurement returns its result as usual.
+    ///
+    /// # Note
+    /// With oversampling (see `set_oversampling`) the remaining measurements
+    /// of a reading are still triggered by `distance`, a single explicit
+    /// trigger starts the whole reading.
+    pub fn set_manual_trigger(&mut self, manual: bool) {
+        self.manual_trigger = manual;
+    }
+
+    /// Prevent the driver from starting new measurements.
+    ///
+    /// This is intended for schedulers coordinating several sensors, e.g.
+    /// letting only one sensor measure at a time to avoid crosstalk. While
+    /// inhibited the sensor is never triggered:
+    ///
+    /// - Polling `distance` while idle returns `SensorError::Inhibited`. A
+    /// measurement already in progress completes and its result is
+    /// returned as usual, but a partially measured oversampled reading (see
+    /// `set_oversampling`) is discarded with `SensorError::Inhibited`.
+    /// - `start_measurement` returns `MeasurementStart::Inhibited`, `fire`,
+    /// `restart` and `measure_with_delay` return `SensorError::Inhibited`
+    /// and `is_connected` returns `false`.
+    ///
+    /// Call `allow` to lift the inhibition.
+    pub fn inhibit(&mut self) {
+        self.inhibited = true;
+    }
+
+    /// Allow the driver to start new measurements again after `inhibit`.
+    pub fn allow(&mut self) {
+        self.inhibited = false;
+    }
+
+    /// Check if the driver is inhibited, see `inhibit`.
+    pub fn is_inhibited(&self) -> bool {
+        self.inhibited
+    }
+
+    /// Start a new measurement without waiting for the result.
+    ///
+    /// This triggers the sensor if it is idle, the same as the first call to
+    /// `distance`, and reports whether the trigger took effect. The result is
+    /// retrieved by polling `distance` as usual.
+    pub fn start_measurement(&mut self) -> MeasurementStart {
+        match self.mode {
+            Mode::Idle if self.inhibited => MeasurementStart::Inhibited,
+            Mode::Idle => {
+                self.trigger();
+                MeasurementStart::Started
+            }
+            _ => MeasurementStart::AlreadyBusy,
+        }
+    }
+
+    /// Abandon any measurement in progress and immediately trigger a new one.
+    ///
+    /// This is a shortcut for `timedout` followed by `start_measurement`,
+    /// useful for e.g. a "measure now" button. Any result not yet read with
+    /// `distance` and any partially completed oversampled reading is
+    /// discarded. The result of the new measurement is retrieved by polling
+    /// `distance` as usual.
+    ///
+    /// # Note
+    /// The driver does not enforce a cooldown between measurements, the
+    /// sensor is triggered right away. If the previous measurement was
+    /// aborted while the sensor was still listening the new measurement may
+    /// pick up its echo, see `consts::MEASUREMENT_CYCLE_US`.
+    ///
+    /// # Errors
+    /// Returns `SensorError::Inhibited`, leaving any measurement in progress
+    /// untouched, if the sensor is inhibited.
+    pub fn restart(&mut self) -> Result<(), SensorError> {
+        if self.inhibited {
+            return Err(SensorError::Inhibited);
+        }
+        self.timedout();
+        self.trigger();
+        Ok(())
+    }
+
+    /// Trigger the sensor without waiting for the result.
+    ///
+    /// This is intended for setups triggering many sensors in quick
+    /// succession and collecting the results later. The result of the
+    /// measurement is retrieved by polling `distance` as usual, which will
+    /// not trigger the sensor again while the measurement is in progress or
+    /// its result is waiting.
+    ///
+    /// # Return
+    /// This function returns `SensorError::WrongMode` if the sensor is not
+    /// idle and `SensorError::Inhibited` if it is inhibited.
+    pub fn fire(&mut self) -> Result<(), SensorError> {
+        match self.start_measurement() {
+            MeasurementStart::Started => Ok(()),
+            MeasurementStart::AlreadyBusy => Err(SensorError::WrongMode),
+            MeasurementStart::Inhibited => Err(SensorError::Inhibited),
+        }
+    }
+
+    /// Update the internal state noting that an external interrupt has
+    /// occurred.
+    ///
+    /// This function updates the internal state in response to an external
+    /// interrupt caused by the sensor. This interface will be removed once
+    /// abstract interrupt handling is supported.
+    ///
+    /// Edges arriving sooner than the debounce time (see
+    /// `HcSr04::set_debounce`) after the previous edge, whether that edge was
+    /// accepted or ignored itself, are considered noise and ignored. Each
+    /// ignored edge flips the level of the echo line, so after an odd number
+    /// of them the line is low again and the next edge is taken as the start
+    /// of the return pulse rather than its end. A pair of glitches during the
+    /// return pulse therefore leaves the measurement in step with the line.
+    ///
+    /// # Interrupt safety
+    /// The timestamp of the rising edge is kept inside the driver, there is
+    /// no need to store it between interrupts. Since this method takes
+    /// `&mut self` the driver must be shared between the interrupt handler
+    /// and the code polling `distance` through a mechanism granting
+    /// exclusive access, e.g. an RTFM resource (see the `f3` example) or a
+    /// critical section. This guarantees that the handler never observes a
+    /// half updated state.
+    ///
+    /// The same holds for nested interrupts. A higher priority handler can
+    /// only reach the driver through the same exclusive access, which
+    /// prevents it from preempting a call already in progress, so the driver
+    /// is never re-entered and needs no run-time guard against it.
+    ///
+    /// # Return
+    /// This function will return `Result::Ok` if called in the correct
+    /// state. Otherwise it will return `Result::Err`.
+    pub fn update(&mut self) -> Result<(), SensorError> {
+        self.mode = match self.mode {
+            Mode::Triggered => self.pulse_start(),
+            Mode::MeasurePulse(start) => {
+                let now = self.timer.now();
+                let hz = self.timer.frequency();
+                let since_edge = now.wrapping_sub(self.last_edge);
+                self.last_edge = now;
+                // Edges this close to the previous edge are too short to be
+                // part of a real return pulse, most likely it is electrical
+                // noise on the echo line. Keep track of whether the line was
+                // left low to stay in step with its level.
+                if since_edge < self.debounce_ticks(hz) {
+                    self.odd_ignored_edges = !self.odd_ignored_edges;
+                    return Ok(());
+                }
+                if self.odd_ignored_edges {
+                    // The line went low during the noise, so this is the
+                    // rising edge of the return pulse
+                    self.pulse_start()
+                } else {
+                    self.measurement(now.wrapping_sub(start), hz)
+                }
+            }
+            // The echo line toggled again after the return pulse, there is
+            // more than one object in view. Unless the falling edge of the
+            // pulse was seen this is just the late end of a pulse which
+            // timed out or got stuck.
+            Mode::Measurement(_) | Mode::Failed(_) | Mode::Idle
+                if self.detect_multiple_echoes && self.pulse_ended =>
+            {
+                self.multiple_echoes = true;
+                return Ok(());
+            }
+            _ => return Err(SensorError::WrongMode),
+        };
+        Ok(())
+    }
+
+    /// Choose whether edges after the return pulse are tracked as echoes.
+    ///
+    /// Only the first return pulse is measured. In cluttered environments
+    /// the echo line may toggle again afterwards because of echoes from
+    /// further objects. When enabled, calls to `update` after the return
+    /// pulse has ended no longer return `SensorError::WrongMode` but are
+    /// recorded instead, see `multiple_echoes`. Disabled by default.
+    ///
+    /// Only edges after a return pulse which was measured to its falling
+    /// edge count. After a timeout (see `timedout`) or
+    /// `SensorError::EchoStuck` the falling edge may still arrive late, it
+    /// and any further edges are reported as `SensorError::WrongMode` as
+    /// usual.
+    ///
+    /// # Note
+    /// This relies on `update` being called for every edge of the echo line,
+    /// including those after the measurement completed.
+    pub fn set_detect_multiple_echoes(&mut self, enable: bool) {
+        self.detect_multiple_echoes = enable;
+        self.multiple_echoes = false;
+    }
+
+    /// Check if the echo line toggled again after the last return pulse.
+    ///
+    /// A `true` result means that more than one object reflected the pulse,
+    /// i.e. the environment is cluttered and the measured distance is that
+    /// of the nearest object. The flag is cleared each time the sensor is
+    /// triggered and is always `false` unless enabled with
+    /// `set_detect_multiple_echoes`.
+    pub fn multiple_echoes(&self) -> bool {
+        self.multiple_echoes
+    }
+
+    /// Call `update` on the sensors whose echo lines have a pending
+    /// interrupt.
+    ///
+    /// This is intended for interrupt handlers shared by several echo lines,
+    /// e.g. one EXTI line for several pins. Bit `i` of `pending` corresponds
+    /// to `sensors[i]`, so the user must arrange the sensors in the order of
+    /// the bits, e.g. by building the mask from the pending flags of each
+    /// echo pin. Bits beyond the end of `sensors` are ignored. The pending
+    /// flags are not cleared, this is left to the caller.
+    ///
+    /// All sensors must be of the same type, i.e. use type erased pins such
+    /// as the downgraded pins of the HAL.
+    ///
+    /// # Return
+    /// A mask with the bits set of the sensors for which `update` returned
+    /// an error, i.e. `0` if all updates succeeded.
+    pub fn update_pending(sensors: &mut [Self], pending: u32) -> u32 {
+        let mut failed = 0;
+        for (i, sensor) in sensors.iter_mut().enumerate().take(32) {
+            if pending & (1 << i) != 0 && sensor.update().is_err() {
+                failed |= 1 << i;
+            }
+        }
+        failed
+    }
+
+    /// Find the sensor with the nearest object, e.g. for obstacle avoidance
+    /// with several sensors.
+    ///
+    /// Only the last completed measurement of each sensor is considered,
+    /// see `peek_distance`, and sensors for which `last_reading_valid` is
+    /// `false` are skipped, i.e. those which timed out, failed or measured a
+    /// distance out of range. If several sensors measured the same distance
+    /// the first of them is returned.
+    ///
+    /// # Return
+    /// The index into `sensors` and the distance of the nearest object, or
+    /// `None` if no sensor has a valid reading.
+    pub fn nearest(sensors: &[Self]) -> Option<(usize, Distance)> {
+        let mut nearest: Option<(usize, Distance)> = None;
+        for (i, sensor) in sensors.iter().enumerate() {
+            if !sensor.last_reading_valid() {
+                continue;
+            }
+            if let Some(dist) = sensor.peek_distance() {
+                if nearest.map_or(true, |(_, near)| dist.mm() < near.mm()) {
+                    nearest = Some((i, dist));
+                }
+            }
+        }
+        nearest
+    }
+
+    /// Update the internal state in response to an interrupt, reading the
+    /// level of the echo pin to decide which edge occurred.
+    ///
+    /// `update` relies on being called exactly once for each edge of the
+    /// return pulse. By reading the echo pin this method instead infers
+    /// whether the interrupt was caused by a rising or falling edge, so that
+    /// a spurious or reordered interrupt does not corrupt the measurement:
+    ///
+    /// - A falling edge before the start of the return pulse is ignored.
+    /// - A rising edge during the return pulse restarts the measurement of
+    /// the pulse, since an earlier falling edge must have been missed.
+    ///
+    /// If the echo pin can't be read in the interrupt handler use `update`
+    /// instead.
+    ///
+    /// # Return
+    /// This function returns `SensorError::WrongMode` if the sensor is not
+    /// expecting a return pulse.
+    pub fn update_from_pin<Echo>(&mut self, echo: &Echo) -> Result<(), SensorError>
+    where
+        Echo: InputPin,
+    {
+        match (self.mode, echo.is_high()) {
+            // Rising edge during pulse, restart pulse measurement
+            (Mode::MeasurePulse(_), true) => {
+                self.mode = self.pulse_start();
+                Ok(())
+            }
+            // Falling edge before the pulse started
+            (Mode::Triggered, false) => Ok(()),
+            // Edge as expected, or wrong mode
+            _ => self.update(),
+        }
+    }
+
+    /// Count spurious edges on the echo line while the sensor is not
+    /// triggered.
+    ///
+    /// The echo pin is sampled `samples` times, 10 µs apart, and the number
+    /// of level changes observed is returned. Since the sensor only drives
+    /// the echo line after a trigger any edge seen is noise, a high count
+    /// indicates electrical interference on the line. This blocks for
+    /// roughly `samples * 10` µs using the `delay`.
+    ///
+    /// # Note
+    /// This should only be called while no measurement is in progress,
+    /// otherwise the return pulse is counted as noise.
+    pub fn measure_noise_floor<Echo>(&mut self, echo: &Echo, samples: u16) -> u16
+    where
+        Echo: InputPin,
+    {
+        let mut edges = 0;
+        let mut last = echo.is_high();
+        for _ in 0..samples {
+            self.delay.delay_us(10);
+            let level = echo.is_high();
+            if level != last {
+                edges += 1;
+                last = level;
+            }
+        }
+        edges
+    }
+
+    /// Take a blocking measurement timing the return pulse with the delay.
+    ///
+    /// This is a fallback for boards without a free timer or interrupt for
+    /// the echo line. The sensor is triggered and the echo pin is sampled
+    /// every `step_us` microseconds, counting the steps the pulse is high.
+    /// The count is converted into a distance the same way as a measurement
+    /// from `update`, so the same errors are returned and the result is also
+    /// available from `peek_distance` and `on_complete`. This blocks for up
+    /// to 60 ms plus the time until the pulse starts.
+    ///
+    /// # Accuracy
+    /// The result is much less accurate than measuring with a timer. The
+    /// pulse width is only known to within one step, e.g. 10 µs or about
+    /// 2 mm, and each step also spends time reading the pin and looping
+    /// which the delay does not account for. The pulse is therefore measured
+    /// too short, typically by several percent depending on the target and
+    /// optimization level. Expect centimeter level accuracy at best. Use a
+    /// small `step_us` to reduce the quantization error, `0` is treated as
+    /// `1`.
+    ///
+    /// # Errors
+    /// If the echo line does not rise within `consts::MAX_ECHO_US` this
+    /// returns `SensorError::ImplausiblePulse` with zero ticks, and if it
+    /// stays high for more than 60 ms `SensorError::EchoStuck`, with ticks
+    /// counted in microseconds. Any measurement in progress is abandoned and
+    /// the sensor is left idle. If the sensor is inhibited this returns
+    /// `SensorError::Inhibited` without doing anything.
+    pub fn measure_with_delay<Echo>(
+        &mut self,
+        echo: &Echo,
+        step_us: u32,
+    ) -> Result<Distance, SensorError>
+    where
+        Echo: InputPin,
+    {
+        if self.inhibited {
+            return Err(SensorError::Inhibited);
+        }
+        let step_us = step_us.max(1);
+        self.timedout();
+        self.trigger();
+        // Wait for the start of the return pulse
+        let mut waited = 0;
+        while !echo.is_high() {
+            if waited >= consts::MAX_ECHO_US {
+                self.timedout();
+                return Err(SensorError::ImplausiblePulse { ticks: 0 });
+            }
+            self.delay.delay_us(step_us);
+            waited += step_us;
+        }
+        // Count the width of the pulse in microseconds
+        let mut width = 0;
+        while echo.is_high() {
+            if width > ECHO_STUCK_MS * 1_000 {
+                let err = SensorError::EchoStuck { ticks: width };
+                self.timedout();
+                self.last_result = Some(Err(err));
+                return Err(err);
+            }
+            self.delay.delay_us(step_us);
+            width += step_us;
+        }
+        let result = match self.measurement(width, 1_000_000) {
+            Mode::Measurement(dist) => Ok(dist),
+            Mode::Failed(err) => Err(err),
+            // `measurement` only produces the two modes above
+            _ => Err(SensorError::WrongMode),
+        };
+        self.mode = Mode::Idle;
+        if let Ok(dist) = result {
+            self.last_reading = Some(dist);
+        }
+        result
+    }
+
+    /// Take `n` blocking measurements and store each successful one in `out`.
+    ///
+    /// Each measurement is taken with `measure_with_delay`, with the same
+    /// `step_us` and accuracy. Unlike oversampling (see `set_oversampling`)
+    /// the measurements are not averaged, the successful ones are written to
+    /// the start of `out` in the order they were taken, leaving any
+    /// post-processing to the caller. Failed measurements are skipped. At
+    /// most `out.len()` measurements are taken.
+    ///
+    /// Between measurements the driver waits `consts::MEASUREMENT_CYCLE_US`
+    /// so that stray echoes from the previous measurement have died out, a
+    /// batch of `n` measurements therefore takes `(n - 1) * 60` ms plus the
+    /// time of the measurements themselves.
+    ///
+    /// # Return
+    /// The number of successful measurements written to `out`.
+    pub fn measure_samples<Echo>(
+        &mut self,
+        echo: &Echo,
+        step_us: u32,
+        n: u8,
+        out: &mut [Distance],
+    ) -> usize
+    where
+        Echo: InputPin,
+    {
+        let n = (n as usize).min(out.len());
+        let mut taken = 0;
+        for i in 0..n {
+            if i > 0 {
+                self.delay.delay_us(consts::MEASUREMENT_CYCLE_US);
+            }
+            if let Ok(dist) = self.measure_with_delay(echo, step_us) {
+                out[taken] = dist;
+                taken += 1;
+            }
+        }
+        taken
+    }
+
+    /// Check if a sensor responds on the echo line.
+    ///
+    /// This triggers a real measurement and samples the echo pin every
+    /// 10 µs for up to `consts::MAX_ECHO_US`, the longest pulse the sensor
+    /// produces. Any working sensor raises the echo line within this window,
+    /// even when no object is in range, so `false` means that the sensor is
+    /// missing, unpowered or disconnected. This blocks for up to roughly
+    /// 38 ms using the `delay`, less if the pulse ends sooner.
+    ///
+    /// # Note
+    /// Any measurement in progress is abandoned and the sensor is left idle.
+    /// The echo of this measurement is not converted into a distance, and
+    /// the outcome of the last measurement, e.g. from `peek_distance` and
+    /// `last_reading_valid`, is kept. If
+    /// interrupts are enabled on the echo line they will cause `update` to
+    /// return `SensorError::WrongMode`. While the sensor is inhibited (see
+    /// `inhibit`) it is not triggered and this returns `false`.
+    pub fn is_connected<Echo>(&mut self, echo: &Echo) -> bool
+    where
+        Echo: InputPin,
+    {
+        if self.inhibited {
+            return false;
+        }
+        // The probe is not a measurement, don't let it clear the outcome of
+        // the last one
+        let last_result = self.last_result;
+        self.timedout();
+        self.trigger();
+        let mut seen = false;
+        for _ in 0..consts::MAX_ECHO_US / 10 {
+            self.delay.delay_us(10);
+            if echo.is_high() {
+                seen = true;
+            } else if seen {
+                // Pulse complete, no need to wait any longer
+                break;
+            }
+        }
+        self.timedout();
+        self.last_result = last_result;
+        seen
+    }
+
+    /// Update the internal state with both edges of the return pulse.
+    ///
+    /// This is an alternative to `update` for input capture peripherals
+    /// which timestamp both edges of the return pulse in one event. The
+    /// timestamps are counter values of a timer running at the frequency set
+    /// with `set_capture_frequency`, by default the frequency of the
+    /// `Clock` given at construction, e.g. values read from the cycle
+    /// counter.
+    ///
+    /// # Return
+    /// This function returns `SensorError::WrongMode` unless the sensor has
+    /// been triggered and is waiting for the return pulse. A pulse shorter
+    /// than the debounce time (see `HcSr04::set_debounce`) is rejected with
+    /// `SensorError::ImplausiblePulse` and the sensor keeps waiting.
+    ///
+    /// If a period is set with `set_counter_period` timestamps which are not
+    /// below it can't come from the counter and are rejected with
+    /// `SensorError::InvalidTimestamp`, the sensor keeps waiting.
+    ///
+    /// # Note
+    /// The counter is assumed to wrap at most once during the pulse, after
+    /// the period set with `set_counter_period` or at 32 bits if none is set.
+    /// A `falling` timestamp before `rising` is therefore taken as a counter
+    /// which has wrapped, but only if the resulting pulse is no longer than
+    /// the 60 ms after which the echo line is considered stuck. Longer pulses
+    /// mean that the timestamps are out of order and are rejected with
+    /// `SensorError::ImplausiblePulse`, the sensor keeps waiting. Use
+    /// `HcSr04::set_max_distance` to catch out of order timestamps giving
+    /// shorter pulses.
+    ///
+    /// The interrupt safety of `update` applies to this method as well.
+    pub fn capture_pair(&mut self, rising: u32, falling: u32) -> Result<(), SensorError> {
+        match self.mode {
+            Mode::Triggered => {
+                let ticks = match self.counter_period {
+                    Some(period) if rising >= period || falling >= period => {
+                        return Err(SensorError::InvalidTimestamp {
+                            timestamp: rising.max(falling),
+                        });
+                    }
+                    // Correct for the counter wrapping during the pulse
+                    Some(period) => counter_span(rising, falling, period),
+                    None => falling.wrapping_sub(rising),
+                };
+                let hz = self.capture_hz.unwrap_or(self.timer.frequency());
+                if ticks < self.debounce_ticks(hz) {
+                    return Err(SensorError::ImplausiblePulse { ticks: ticks });
+                }
+                // A wrapped counter can't explain a pulse this long, the
+                // timestamps are out of order
+                let stuck_ticks = hz as u64 * ECHO_STUCK_MS as u64 / 1_000;
+                if falling < rising && ticks as u64 > stuck_ticks {
+                    return Err(SensorError::ImplausiblePulse { ticks: ticks });
+                }
+                self.mode = self.measurement(ticks, hz);
+                Ok(())
+            }
+            _ => Err(SensorError::WrongMode),
+        }
+    }
+
+    /// Update the internal state with edge timestamps captured into a buffer.
+    ///
+    /// This is intended for input capture peripherals writing the timestamp
+    /// of each edge on the echo line into memory through DMA. `edges` should
+    /// hold the timestamps in the order they were captured, starting with
+    /// the rising edge of the return pulse followed by its falling edge. The
+    /// timestamps are interpreted as for `capture_pair`, which is used to
+    /// measure the first two edges.
+    ///
+    /// More than two edges mean that the echo line toggled again after the
+    /// return pulse. The extra edges are ignored, but if enabled with
+    /// `set_detect_multiple_echoes` they are reported by `multiple_echoes`.
+    ///
+    /// # Return
+    /// This function returns the same errors as `capture_pair`. A buffer with
+    /// fewer than two edges is rejected with `SensorError::ImplausiblePulse`
+    /// with zero ticks and the sensor keeps waiting for the return pulse.
+    pub fn capture_from_buffer(&mut self, edges: &[u32]) -> Result<(), SensorError> {
+        if edges.len() < 2 {
+            return match self.mode {
+                Mode::Triggered => Err(SensorError::ImplausiblePulse { ticks: 0 }),
+                _ => Err(SensorError::WrongMode),
+            };
+        }
+        self.capture_pair(edges[0], edges[1])?;
+        if edges.len() > 2 && self.detect_multiple_echoes {
+            self.multiple_echoes = true;
+        }
+        Ok(())
+    }
+
+    /// Update the internal state with an edge timestamp from a 16-bit timer.
+    ///
+    /// This is an alternative to `update` for input capture peripherals with
+    /// a 16-bit counter, e.g. a prescaled general purpose timer. Call it with
+    /// the captured counter value for each edge of the return pulse, the
+    /// first call is taken as the rising edge and the second as the falling
+    /// edge.
+    ///
+    /// # Arguments
+    /// - `ts` is the captured counter value.
+    /// - `top` is the highest value of the counter, i.e. its auto-reload
+    /// value, the counter counts from `0` to `top` before wrapping. This is
+    /// used to correct for the counter wrapping during the pulse, which is
+    /// assumed to happen at most once. The period of the counter, `top + 1`,
+    /// is stored as with `set_counter_period` and used by `capture_pair` as
+    /// well.
+    ///
+    /// The counter is assumed to run at the frequency set with
+    /// `set_capture_frequency`, or the frequency of the `Clock` if none
+    /// is set. Edges sooner than the debounce time (see `set_debounce`) after
+    /// the previous edge are ignored the same way as by `update`.
+    ///
+    /// # Return
+    /// This function returns `SensorError::WrongMode` if the sensor is not
+    /// expecting a return pulse. A timestamp above `top` can't come from the
+    /// counter and is rejected with `SensorError::InvalidTimestamp`. If `top`
+    /// is lowered during the pulse so that the earlier edges are no longer
+    /// within the period the pulse can't be measured and the edge is
+    /// rejected with `SensorError::ImplausiblePulse`. In both cases the
+    /// sensor keeps waiting.
+    pub fn capture_with_period(&mut self, ts: u16, top: u16) -> Result<(), SensorError> {
+        let period = top as u32 + 1;
+        let ts = ts as u32;
+        self.mode = match self.mode {
+            Mode::Triggered | Mode::CapturePulse(_) if ts >= period => {
+                return Err(SensorError::InvalidTimestamp { timestamp: ts });
+            }
+            Mode::Triggered => {
+                self.counter_period = Some(period);
+                self.last_edge = ts;
+                self.odd_ignored_edges = false;
+                Mode::CapturePulse(ts as u16)
+            }
+            Mode::CapturePulse(start) => {
+                self.counter_period = Some(period);
+                let start = start as u32;
+                if start >= period || self.last_edge >= period {
+                    return Err(SensorError::ImplausiblePulse { ticks: 0 });
+                }
+                let hz = self.capture_hz.unwrap_or(self.timer.frequency());
+                let since_edge = counter_span(self.last_edge, ts, period);
+                self.last_edge = ts;
+                // Ignore noise the same way as `update`, keeping track of
+                // whether the line was left low
+                if since_edge < self.debounce_ticks(hz) {
+                    self.odd_ignored_edges = !self.odd_ignored_edges;
+                    return Ok(());
+                }
+                if self.odd_ignored_edges {
+                    // This is the rising edge of the return pulse
+                    self.odd_ignored_edges = false;
+                    Mode::CapturePulse(ts as u16)
+                } else {
+                    self.measurement(counter_span(start, ts, period), hz)
+                }
+            }
+            _ => return Err(SensorError::WrongMode),
+        };
+        Ok(())
+    }
+
+    /// Set the period of the counter used with `capture_pair`.
+    ///
+    /// The period is the number of ticks before the counter wraps, i.e. the
+    /// counter counts from `0` to `period - 1`, and is used to correct pulses
+    /// during which the counter wrapped. Call this again whenever the timer
+    /// is reconfigured, the new period is used from the next capture. Pass
+    /// `None` for a counter which wraps at 32 bits, the default.
+    /// `capture_with_period` sets the period from its `top` argument.
+    pub fn set_counter_period(&mut self, period: Option<u32>) {
+        self.counter_period = period;
+    }
+
+    /// Get the period of the counter used with `capture_pair` and
+    /// `capture_with_period`.
+    pub fn counter_period(&self) -> Option<u32> {
+        self.counter_period
+    }
+
+    /// Set the frequency of the timer used with `capture_pair` and
+    /// `capture_with_period`.
+    ///
+    /// This must match the rate the captured counter increments at after any
+    /// prescaling. Defaults to the frequency of the `Clock`. A frequency of
+    /// 0 Hz can't be converted into time and is ignored, keeping the previous
+    /// frequency.
+    pub fn set_capture_frequency(&mut self, hz: Hertz) {
+        if hz.0 != 0 {
+            self.capture_hz = Some(hz.0);
+        }
+    }
+
+    /// Update the internal state from a sample of the echo line.
+    ///
+    /// This is an alternative to `update` for setups where the echo line can't
+    /// generate interrupts, e.g. when the echo is sampled through an ADC and
+    /// compared against a threshold. Call this periodically with `true` if
+    /// the echo is currently high and `false` otherwise, the driver detects
+    /// the edges of the return pulse and feeds them to the state machine the
+    /// same way `update` does. Samples which don't represent an edge are
+    /// ignored, `update` is only called when the level changes so that edges
+    /// it ignores as noise (see `set_debounce`) keep it in step with the
+    /// line. To debounce the echo line an edge can be required to persist
+    /// for several samples, see `set_poll_threshold`.
+    ///
+    /// # Note
+    /// Each edge is only detected at the next sample, so the accuracy depends
+    /// on the sampling rate. With a sampling period of `T` µs each edge may be
+    /// off by up to `T` µs which corresponds to roughly `T / 5.8` mm, e.g.
+    /// sampling at 10 kHz gives readings within ±17 mm.
+    pub fn poll_echo(&mut self, high: bool) -> Result<(), SensorError> {
+        let edge = match (self.mode, high) {
+            // Rising edge of return pulse
+            (Mode::Triggered, true) => true,
+            // Falling edge of return pulse, or the rising edge if the line
+            // was left low by noise ignored by `update`, in which case only
+            // a high sample is a change of the level
+            (Mode::MeasurePulse(_), _) => high == self.odd_ignored_edges,
+            // No edge, or not expecting a return pulse
+            _ => false,
+        };
+        if !edge {
+            self.poll_count = 0;
+            return Ok(());
+        }
+        // Only accept the edge once the new level has been seen in enough
+        // consecutive samples
+        self.poll_count = self.poll_count.saturating_add(1);
+        if self.poll_count < self.poll_threshold {
+            return Ok(());
+        }
+        self.poll_count = 0;
+        self.update()
+    }
+
+    /// Set the number of consecutive samples needed to accept an edge in
+    /// `poll_echo`.
+    ///
+    /// With a threshold of `n` an edge is only accepted once the echo line
+    /// has been at the new level for `n` samples in a row, so glitches
+    /// shorter than `n` samples are ignored. Since both edges of the return
+    /// pulse are delayed by the same number of samples the measured width is
+    /// not biased, but the accuracy of each edge is still limited by the
+    /// sampling period as described for `poll_echo`, and the result arrives
+    /// `n - 1` samples later. Defaults to `1`, i.e. no debouncing.
+    pub fn set_poll_threshold(&mut self, n: u8) {
+        self.poll_threshold = n;
+        self.poll_count = 0;
+    }
+
+    /// Set the minimum time in microseconds between the edges of the return
+    /// pulse.
+    ///
+    /// Edges arriving sooner than this after the previous edge of the return
+    /// pulse are ignored by `update`. The default of 100 µs is just below the pulse
+    /// width of an object at the minimum range of 2 cm. Increase it if noise
+    /// is still causing short readings, at the cost of not being able to
+    /// measure objects closer than `us / 58` centimeters. Setting it to `0`
+    /// disables debouncing.
+    pub fn set_debounce(&mut self, us: u32) {
+        self.debounce_us = us;
+    }
+
+    /// Borrow the filter applied to the readings from `distance`.
+    ///
+    /// This allows e.g. inspecting a `stats::ReadingLog` given to
+    /// `HcSr04Builder::filter`.
+    pub fn filter(&self) -> &Filt {
+        &self.filter
+    }
+
+    /// Mutably borrow the filter applied to the readings from `distance`.
+    ///
+    /// This allows e.g. resetting the filter with `Filter::reset` after the
+    /// sensor has been moved, so that stale readings don't pull the next
+    /// ones towards the old position.
+    pub fn filter_mut(&mut self) -> &mut Filt {
+        &mut self.filter
+    }
+
+    /// Borrow the trigger pin.
+    ///
+    /// This is intended for advanced use where the pin is needed for
+    /// something else for a short while, e.g. when it is shared with another
+    /// bus, without tearing down the driver.
+    ///
+    /// # Note
+    /// The driver assumes that the trigger pin is low whenever it is not
+    /// triggering the sensor. The pin must therefore be set low again before
+    /// the borrow ends, and it should not be used while a measurement is in
+    /// progress.
+    pub fn pin_mut(&mut self) -> &mut Pin {
+        &mut self.pin
+    }
+
+    /// Get the total time in microseconds the sensor has spent measuring.
+    ///
+    /// This counts the time from each trigger pulse until the measurement
+    /// completed, failed or timed out, and is useful for power budgeting.
+    /// Measured with the `Clock`, see `reset_active_time` to start over.
+    pub fn active_time_us(&self) -> u64 {
+        // Whole seconds and the remainder separately, so that the
+        // multiplication can't overflow however long the sensor was active
+        let hz = self.timer.frequency().max(1) as u64;
+        self.active_ticks / hz * 1_000_000 + self.active_ticks % hz * 1_000_000 / hz
+    }
+
+    /// Reset the time counted by `active_time_us` to zero.
+    pub fn reset_active_time(&mut self) {
+        self.active_ticks = 0;
+    }
+
+    /// Get the shortest and longest trigger pulse sent, in nanoseconds.
+    ///
+    /// The width of each trigger pulse is measured with the `Clock` from
+    /// just before the trigger pin is set high until just after it is set
+    /// low again. The difference between the two shows how much the pulse
+    /// jitters on the hardware, e.g. due to interrupts during the delay.
+    /// Returns `None` until the sensor has been triggered.
+    pub fn trigger_jitter_ns(&self) -> Option<(u32, u32)> {
+        let hz = self.timer.frequency().max(1) as u64;
+        self.trigger_widths.map(|(min, max)| {
+            (
+                (min as u64 * 1_000_000_000 / hz) as u32,
+                (max as u64 * 1_000_000_000 / hz) as u32,
+            )
+        })
+    }
+
+    /// Forget the trigger pulse widths recorded for `trigger_jitter_ns`.
+    pub fn reset_trigger_jitter(&mut self) {
+        self.trigger_widths = None;
+    }
+
+    /// Get the distance in micrometers represented by one tick of the timer.
+    ///
+    /// This is the theoretical resolution of the measurement at the
+    /// frequency of the `Clock`, rounded up. Higher frequencies give
+    /// finer resolution, e.g. a 1 MHz timer gives 172 µm while a 72 MHz timer
+    /// gives 3 µm. Since distances are reported in whole millimeters the
+    /// resolution of readings is never better than 1 mm, a result above
+    /// `1_000` means that the timer limits the resolution.
+    pub fn resolution_um(&self) -> u32 {
+        let hz = self.timer.frequency().max(1) as u64;
+        ((self.speed_factor() as u64 * 1_000 + hz - 1) / hz) as u32
+    }
+
+    /// Get the worst case time in microseconds of one full measurement.
+    ///
+    /// This is the sum of the settle delay (see `set_settle_delay`), the
+    /// trigger pulse, the longest possible return pulse and the time to wait
+    /// after it before triggering again, so that the next measurement does
+    /// not pick up stray echoes. The last two add up to
+    /// `consts::MEASUREMENT_CYCLE_US`.
+    pub fn cycle_time_us(&self) -> u32 {
+        let cooldown = consts::MEASUREMENT_CYCLE_US - consts::MAX_ECHO_US;
+        self.settle_us
+            .saturating_add(consts::TRIGGER_WIDTH_US)
+            .saturating_add(consts::MAX_ECHO_US)
+            .saturating_add(cooldown)
+    }
+
+    /// Get the highest sustainable measurement rate in whole Hz.
+    ///
+    /// This is the inverse of `cycle_time_us`, rounded down, e.g. 16 Hz
+    /// without a settle delay. With oversampling (see `set_oversampling`)
+    /// each reading takes several measurements, so the rate of readings is
+    /// this divided by the oversampling factor.
+    pub fn max_rate_hz(&self) -> u32 {
+        1_000_000 / self.cycle_time_us()
+    }
+
+    /// Get the speed factor used to convert the return pulse into distance.
+    ///
+    /// The factor is half the speed of sound in millimeters per second, see
+    /// `DEFAULT_SPEED_FACTOR`. It changes with `set_temperature`, and is
+    /// doubled to the full speed of sound when `set_one_way` is enabled.
+    pub fn speed_factor(&self) -> u32 {
+        if self.one_way {
+            self.speed_factor.saturating_mul(2)
+        } else {
+            self.speed_factor
+        }
+    }
+
+    /// Choose whether the pulse travels one way instead of there and back.
+    ///
+    /// By default the sensor is assumed to be monostatic, i.e. the pulse
+    /// travels to the object and back to the same module, so the distance is
+    /// half the path travelled. For unusual setups with a separate
+    /// transmitter and receiver facing each other the path is one way and
+    /// the distance is the full path, enable this to double the distance
+    /// accordingly.
+    pub fn set_one_way(&mut self, one_way: bool) {
+        self.one_way = one_way;
+    }
+
+    /// Get the width of the last completed return pulse in timer ticks.
+    ///
+    /// This is the raw measurement before any conversion and is useful when
+    /// debugging timer or clock issues. The tick rate is the frequency of the
+    /// timer the pulse was measured with, i.e. the `Clock` given at
+    /// construction or the frequency set with `set_capture_frequency` for
+    /// captured timestamps. Returns `None` until a return pulse has been
+    /// completely measured.
+    pub fn last_delta_ticks(&self) -> Option<u32> {
+        self.last_ticks.map(|(ticks, _)| ticks)
+    }
+
+    /// Get the current state of the driver as a small integer.
+    ///
+    /// This is intended for compact telemetry, the codes are stable and will
+    /// not be reassigned:
+    ///
+    /// - `0`: idle, ready to start a new measurement
+    /// - `1`: triggered, waiting for the return pulse
+    /// - `2`: measuring the return pulse with the `Clock`
+    /// - `3`: measuring the return pulse with captured timestamps, see
+    /// `capture_with_period`
+    /// - `4`: measurement complete, waiting to be read with `distance`
+    /// - `5`: measurement failed, waiting to be read with `distance`
+    pub fn state_code(&self) -> u8 {
+        match self.mode {
+            Mode::Idle => 0,
+            Mode::Triggered => 1,
+            Mode::MeasurePulse(_) => 2,
+            Mode::CapturePulse(_) => 3,
+            Mode::Measurement(_) => 4,
+            Mode::Failed(_) => 5,
+        }
+    }
+
+    /// Get the distance of the last completed measurement.
+    ///
+    /// Unlike `distance` this neither triggers the sensor nor consumes the
+    /// result, so it can be called any number of times, e.g. by several
+    /// consumers of the same sensor. The result is available as soon as the
+    /// measurement completes, before `distance` is polled. With oversampling
+    /// (see `set_oversampling`) this is the last single measurement rather
+    /// than the averaged reading.
+    ///
+    /// Returns `None` until a measurement has completed, and if the last
+    /// measurement failed or timed out.
+    pub fn peek_distance(&self) -> Option<Distance> {
+        match self.last_result {
+            Some(Ok(dist)) => Some(dist),
+            _ => None,
+        }
+    }
+
+    /// Get the time in microseconds the last measurement took.
+    ///
+    /// This is the time from the trigger pulse until the end of the return
+    /// pulse was processed, measured with the `Clock`. It grows with the
+    /// distance of the target, from under a millisecond for near targets to
+    /// almost 40 ms when no object is detected, and includes any delay in
+    /// handling the interrupt of the falling edge. Measurements which failed
+    /// with an error are included as long as the return pulse ended, those
+    /// which timed out or where the echo got stuck are not.
+    ///
+    /// Returns `None` until a measurement has completed.
+    pub fn last_latency_us(&self) -> Option<u32> {
+        self.last_latency_us
+    }
+
+    /// Get the intermediate values of the last conversion.
+    ///
+    /// This shows exactly how the last distance was derived from the return
+    /// pulse, which helps when debugging distances which look wrong. The
+    /// distance is before any check with `set_reading_filter`. Returns
+    /// `None` until a return pulse has passed the checks of the driver and
+    /// been converted, later failed measurements keep the last conversion.
+    pub fn last_computation(&self) -> Option<Computation> {
+        self.last_computation
+    }
+
+    /// Check if the last measurement can be trusted.
+    ///
+    /// This returns `true` only if all of the following hold:
+    ///
+    /// - The last measurement completed, i.e. it did not time out (see
+    /// `timedout`) and the echo line was not stuck.
+    /// - The return pulse was plausible and an object was detected.
+    /// - The distance is within the range of the sensor, from `min_range`
+    /// up to the maximum distance set with `set_max_distance`, or
+    /// `consts::MAX_RANGE_MM` if none is set.
+    ///
+    /// Before any measurement has completed this returns `false`.
+    pub fn last_reading_valid(&self) -> bool {
+        let min = self.min_range().mm();
+        let max = self.max_distance.map_or(consts::MAX_RANGE_MM, |d| d.mm());
+        match self.last_result {
+            Some(Ok(dist)) => min <= dist.mm() && dist.mm() <= max,
+            _ => false,
+        }
+    }
+
+    /// Get a snapshot of the current configuration of the driver.
+    ///
+    /// The frequency is the one set with `set_capture_frequency`, or the
+    /// frequency of the `Clock` if none is set. The maximum range is the
+    /// one set with `set_max_distance`, or `consts::MAX_RANGE_MM` if none is
+    /// set.
+    pub fn config(&self) -> Config {
+        Config {
+            frequency_hz: self.capture_hz.unwrap_or(self.timer.frequency()),
+            width_offset_ns: self.width_offset_ns,
+            temperature: self.temperature,
+            trigger_width_us: consts::TRIGGER_WIDTH_US,
+            min_range_mm: self.min_range().mm(),
+            max_range_mm: self.max_distance.map_or(consts::MAX_RANGE_MM, |d| d.mm()),
+            speed_factor: self.speed_factor(),
+            debounce_us: self.debounce_us,
+            settle_us: self.settle_us,
+            pulse_limits_us: self.pulse_limits,
+            oversampling: self.oversampling.max(1),
+        }
+    }
+
+    /// Get the shortest distance the sensor can reliably measure.
+    ///
+    /// This is `consts::MIN_RANGE_MM` unless scaling is enabled with
+    /// `set_scale_min_range`.
+    pub fn min_range(&self) -> Distance {
+        if !self.scale_min_range {
+            return Distance(consts::MIN_RANGE_MM);
+        }
+        let scaled = consts::MIN_RANGE_MM as u64 * self.speed_factor as u64
+            / DEFAULT_SPEED_FACTOR as u64;
+        Distance(saturate(scaled))
+    }
+
+    /// Choose whether the minimum range follows the temperature.
+    ///
+    /// The minimum range is set by the time the transducer needs to stop
+    /// ringing after sending the pulse, during which echoes can't be
+    /// received. This time is assumed to be constant, so the minimum range
+    /// is taken to be proportional to the speed of sound, i.e.
+    /// `consts::MIN_RANGE_MM` scaled by the current speed factor relative to
+    /// `DEFAULT_SPEED_FACTOR`. E.g. at -40°C it shrinks by about 10%. The
+    /// adjustment is at most a few millimeters and only matters for precise
+    /// operation in very cold or hot conditions. Disabled by default, which
+    /// keeps `consts::MIN_RANGE_MM` regardless of temperature.
+    ///
+    /// # Note
+    /// This only changes the minimum range reported by `min_range` and
+    /// `config`, and the check of `last_reading_valid`. Readings returned by
+    /// `distance` are neither clamped nor rejected below the minimum range.
+    pub fn set_scale_min_range(&mut self, enable: bool) {
+        self.scale_min_range = enable;
+    }
+
+    /// Reset the calibration of the driver to its defaults.
+    ///
+    /// Everything affecting how a return pulse is converted into a distance
+    /// is restored:
+    ///
+    /// - Any temperature given to `set_temperature` or speed of sound given
+    /// to `set_speed_of_sound_mps` is forgotten and the speed factor for
+    /// `DEFAULT_TEMPERATURE` is used.
+    /// - The table set with `set_speed_table` and the function set with
+    /// `with_temperature_fn` are removed.
+    /// - `set_one_way` is disabled.
+    /// - The offset set with `set_width_offset_ns` is reset to `0`.
+    ///
+    /// Any measurement in progress, the trigger pin and the settings which
+    /// only decide whether a pulse is accepted (debounce, pulse limits,
+    /// maximum distance and capture frequency) are left untouched, as is the
+    /// settle delay.
+    pub fn reset_calibration(&mut self) {
+        self.temperature = None;
+        self.speed_factor = speed_factor_at(DEFAULT_TEMPERATURE);
+        self.speed_table = None;
+        self.temperature_fn = None;
+        self.one_way = false;
+        self.width_offset_ns = 0;
+    }
+
+    /// Compensate for the temperature of the air in °C.
+    ///
+    /// The speed of sound changes with temperature which affects the
+    /// distance calculated from the return pulse. Until this is called the
+    /// driver assumes `DEFAULT_TEMPERATURE`.
+    ///
+    /// Temperatures below freezing are supported, e.g. at -20°C the speed of
+    /// sound is about 319 m/s and at -40°C about 307 m/s, compared to 343 m/s
+    /// at 20°C. The compensation uses a linear approximation which is
+    /// accurate to within 1 m/s from -40°C to 40°C. Temperatures are clamped
+    /// to -100°C to 200°C.
+    ///
+    /// If a table is set with `set_speed_table` the speed factor is looked up
+    /// in the table instead.
+    pub fn set_temperature(&mut self, celsius: i16) {
+        self.temperature = Some(celsius);
+        self.speed_factor = self.speed_table
+            .and_then(|table| speed_factor_from_table(table, celsius))
+            .unwrap_or_else(|| speed_factor_at(celsius));
+    }
+
+    /// Set the speed of sound in meters per second.
+    ///
+    /// This sets the speed factor directly rather than through temperature
+    /// compensation, e.g. for use in water (about 1480 m/s) or other media.
+    /// The speed factor is half of `mps` converted to millimeters per second,
+    /// so `set_speed_of_sound_mps(343)` gives a factor of `171_500`. Any
+    /// temperature given to `set_temperature` is forgotten, use
+    /// `reset_calibration` to return to the default.
+    ///
+    /// # Note
+    /// A function given to `with_temperature_fn` overrides this on the next
+    /// conversion.
+    pub fn set_speed_of_sound_mps(&mut self, mps: u32) {
+        self.temperature = None;
+        self.speed_factor = mps.saturating_mul(500);
+    }
+
+    /// Use a table of precomputed speed factors for temperature compensation.
+    ///
+    /// Each entry of `table` is the lowest temperature in °C of a range and
+    /// the speed factor (see `DEFAULT_SPEED_FACTOR`) to use within it, the
+    /// entries must be sorted by temperature. `set_temperature` then picks
+    /// the entry of the range containing the temperature, temperatures below
+    /// the first range use the first entry. This makes compensation, which
+    /// also runs from `update` with `with_temperature_fn`, a short
+    /// deterministic lookup. `SPEED_FACTOR_TABLE` covers -40°C to 64°C in
+    /// 5°C ranges.
+    ///
+    /// Pass `None`, or an empty table, to use the linear approximation
+    /// described in `set_temperature`. The current speed factor is updated
+    /// right away if a temperature has been set.
+    ///
+    /// # Note
+    /// The accuracy is limited by the granularity of the table, with factors
+    /// computed for the middle of each range the error is up to half a
+    /// range, for 5°C ranges about 1.5 m/s or 0.45% of the speed of sound.
+    /// Finer ranges are more accurate at the cost of memory and a longer
+    /// lookup.
+    pub fn set_speed_table(&mut self, table: Option<&'static [(i16, u32)]>) {
+        self.speed_table = table;
+        if let Some(celsius) = self.temperature {
+            self.set_temperature(celsius);
+        }
+    }
+
+    /// Set the number of measurements averaged for each reading.
+    ///
+    /// With `n` larger than one each reading returned from `distance` is the
+    /// mean of `n` measurements, trading latency for less noise. If any of
+    /// the measurements fail the error is returned and the measurements
+    /// taken so far are discarded, the same happens when a measurement times
+    /// out (see `timedout`). Defaults to `1`, `0` is treated as `1`.
+    ///
+    /// # Note
+    /// The next measurement of a reading is triggered on the first poll of
+    /// `distance` after the previous one completed. The driver does not wait
+    /// for the recommended measurement cycle (`consts::MEASUREMENT_CYCLE_US`)
+    /// in between, so polling `distance` in a tight loop can pick up stray
+    /// echoes from the previous measurement. Either poll at a suitable rate
+    /// or use `set_settle_delay` to space the measurements.
+    pub fn set_oversampling(&mut self, n: u8) {
+        self.oversampling = n;
+        self.discard_burst();
+    }
+
+    /// Get the variance of the pulse widths of the last oversampled reading.
+    ///
+    /// The variance is in ticks² of the timer measuring the pulse, see
+    /// `last_delta_ticks`, over all measurements of the last reading
+    /// averaged with oversampling (see `set_oversampling`). Returns `None`
+    /// until such a reading has completed.
+    ///
+    /// # Note
+    /// This is an experimental diagnostic. For a stationary target the
+    /// variation between pulses may hint at the size or shape of the target,
+    /// but the driver makes no attempt to interpret it.
+    pub fn pulse_width_variance(&self) -> Option<u64> {
+        self.pulse_variance.map(|(variance, _)| variance)
+    }
+
+    /// Check the timing of the last oversampled reading for instability.
+    ///
+    /// With the sensor pointed at a static target the pulse widths of an
+    /// oversampled reading (see `set_oversampling`) should hardly vary. This
+    /// compares the standard deviation of the pulse widths, see
+    /// `pulse_width_variance`, against `max_std_dev_us` microseconds. A
+    /// larger deviation indicates an unstable or drifting timer, lost or
+    /// late interrupts, or a moving target. The bound is converted into ticks
+    /// at the frequency the pulses of the reading were measured with.
+    ///
+    /// A good setup typically shows a standard deviation of a few
+    /// microseconds, i.e. a millimeter or less, `20` µs (about 3 mm) is a
+    /// reasonable bound to start from. Use at least 8 measurements per
+    /// reading for a meaningful result.
+    pub fn timing_health(&self, max_std_dev_us: u32) -> TimingHealth {
+        match self.pulse_variance {
+            Some((variance, hz)) => {
+                // Compare variances to avoid a square root
+                let bound = max_std_dev_us as u64 * hz as u64 / 1_000_000;
+                if variance > bound.saturating_mul(bound) {
+                    TimingHealth::Unstable
+                } else {
+                    TimingHealth::Stable
+                }
+            }
+            None => TimingHealth::Unknown,
+        }
+    }
+
+    /// Correct the width of the return pulse by `ns` nanoseconds.
+    ///
+    /// This compensates for an asymmetry between the edges of the echo line,
+    /// i.e. a rising edge which is registered later than the falling edge or
+    /// the other way around. This happens e.g. when the rise and fall times
+    /// on a long cable differ, or when the interrupt of one edge is handled
+    /// with more latency than the other, and makes every pulse appear longer
+    /// by the same amount. A delay common to both edges, such as the plain
+    /// propagation delay of the cable, cancels out and needs no correction.
+    ///
+    /// The offset is subtracted from the width of the return pulse before it
+    /// is validated, see `set_pulse_limits` and `set_max_distance`, and
+    /// converted into a distance. Since this is a correction in time, not
+    /// distance, it stays correct when the speed of sound changes with
+    /// temperature. Defaults to `0`.
+    pub fn set_width_offset_ns(&mut self, ns: u32) {
+        self.width_offset_ns = ns;
+    }
+
+    /// Correct for the delay added by the cabling to the sensor, in
+    /// nanoseconds.
+    ///
+    /// This is the same correction as `set_width_offset_ns`, under the name
+    /// users looking for a cable delay setting expect. It is kept separate
+    /// from a mounting offset, which shifts every reading by a fixed
+    /// distance: this is a correction in time applied to the width of the
+    /// return pulse, and only the part of the delay by which the edges of
+    /// the echo line differ should be given.
+    pub fn set_cable_delay_ns(&mut self, ns: u32) {
+        self.set_width_offset_ns(ns);
+    }
+
+    /// Select the variant of the sensor connected.
+    ///
+    /// This applies the defaults of the variant, currently the pulse limits
+    /// (see `set_pulse_limits`), overriding any limits set before. Defaults
+    /// to `SensorVariant::HcSr04`.
+    pub fn set_variant(&mut self, variant: SensorVariant) {
+        self.variant = variant;
+        self.pulse_limits = variant.pulse_limits_us();
+    }
+
+    /// Get the variant of the sensor selected with `set_variant`.
+    pub fn variant(&self) -> SensorVariant {
+        self.variant
+    }
+
+    /// Set the shortest and longest valid return pulse in microseconds.
+    ///
+    /// Return pulses outside of these limits, which are not long enough to
+    /// signal that no object was detected, are reported as
+    /// `SensorError::OutOfSpec` from `distance`. The defaults are
+    /// `consts::MIN_VALID_ECHO_US` and `consts::MAX_VALID_ECHO_US`, which
+    /// correspond to the range of 2 cm to 4 m from the datasheet. Change them
+    /// for variants of the sensor with a different range.
+    pub fn set_pulse_limits(&mut self, min_us: u32, max_us: u32) {
+        self.pulse_limits = (min_us, max_us);
+    }
+
+    /// Set the largest distance considered plausible.
+    ///
+    /// Return pulses longer than what an object at `max` would produce are
+    /// reported as `SensorError::ImplausiblePulse` from `distance` instead of
+    /// being converted into a very large distance. This catches timestamps
+    /// which can't be trusted, e.g. due to a misconfigured timer frequency.
+    /// The sensor is rated up to 4 m. Defaults to `None`, i.e. no check.
+    ///
+    /// Regardless of this setting pulses longer than the sensor ever
+    /// produces, over 60 ms, are reported as `SensorError::ImplausiblePulse`
+    /// before they can be mistaken for `SensorError::NoObjectDetected`, e.g.
+    /// when garbage timestamps are passed to `capture_pair`.
+    pub fn set_max_distance(&mut self, max: Option<Distance>) {
+        self.max_distance = max;
+    }
+
+    /// Get the temperature in °C used for compensation.
+    ///
+    /// Returns `None` if no temperature has been set, in which case
+    /// `DEFAULT_TEMPERATURE` is used. See also `speed_factor` for the
+    /// resulting factor.
+    pub fn temperature(&self) -> Option<i16> {
+        self.temperature
+    }
+
+    /// Set a delay in microseconds to wait before each trigger pulse.
+    ///
+    /// Some clone modules misbehave when triggered immediately after power
+    /// up or after the previous measurement, this gives them time to settle.
+    /// The delay is performed with the `delay` given at construction and
+    /// blocks inside `distance` when a new measurement is started. Defaults
+    /// to `0`, i.e. no delay.
+    pub fn set_settle_delay(&mut self, us: u32) {
+        self.settle_us = us;
+    }
+
+    /// Notify the driver that the current measurement has timed out.
+    ///
+    /// This abandons any measurement in progress and returns the sensor to
+    /// idle so that the next call to `distance` triggers a new measurement.
+    /// Use this when managing a timeout timer outside of the driver, drivers
+    /// created with `HcSr04::with_timeout` call this automatically. With
+    /// oversampling (see `set_oversampling`) the measurements of the reading
+    /// taken so far are discarded, the next reading starts over.
+    ///
+    /// # Note
+    /// If the sensor is still producing a pulse when this is called the
+    /// resulting interrupt will cause `update` to return
+    /// `SensorError::WrongMode`.
+    pub fn timedout(&mut self) {
+        self.mode = Mode::Idle;
+        self.discard_burst();
+        self.finish();
+        self.pulse_ended = false;
+        self.last_result = None;
+    }
+
+    /// Capture the internal state of the driver.
+    ///
+    /// This is intended for testing and replay of specific conditions of the
+    /// internal state machine and is only available with the `testing`
+    /// feature.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn debug_snapshot(&self) -> Snapshot {
+        Snapshot {
+            mode: self.mode,
+            triggered_at: self.triggered_at,
+            cycle_start: self.cycle_start,
+            last_edge: self.last_edge,
+            odd_ignored_edges: self.odd_ignored_edges,
+            poll_count: self.poll_count,
+            burst_len: self.burst_len,
+            burst_sum: self.burst_sum,
+            burst_ticks: self.burst_ticks,
+            last_ticks: self.last_ticks,
+            last_result: self.last_result,
+            last_reading: self.last_reading,
+            multiple_echoes: self.multiple_echoes,
+            pulse_ended: self.pulse_ended,
+        }
+    }
+
+    /// Restore the internal state of the driver from a `Snapshot`.
+    ///
+    /// # Note
+    /// Restoring a snapshot does not touch the trigger pin or any timers, a
+    /// snapshot taken while a measurement was in progress will therefore
+    /// expect interrupts which may never arrive. The timestamps in the
+    /// snapshot are counts of the `Clock`, they are only meaningful when
+    /// restored onto a driver using the same clock.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn debug_restore(&mut self, snapshot: Snapshot) {
+        self.mode = snapshot.mode;
+        self.triggered_at = snapshot.triggered_at;
+        self.cycle_start = snapshot.cycle_start;
+        self.last_edge = snapshot.last_edge;
+        self.odd_ignored_edges = snapshot.odd_ignored_edges;
+        self.poll_count = snapshot.poll_count;
+        self.burst_len = snapshot.burst_len;
+        self.burst_sum = snapshot.burst_sum;
+        self.burst_ticks = snapshot.burst_ticks;
+        self.last_ticks = snapshot.last_ticks;
+        self.last_result = snapshot.last_result;
+        self.last_reading = snapshot.last_reading;
+        self.multiple_echoes = snapshot.multiple_echoes;
+        self.pulse_ended = snapshot.pulse_ended;
+    }
+
+    /// Result of polling `distance` while a measurement is in progress
+    fn busy(&self) -> nb::Result<Distance, SensorError> {
+        match self.last_reading {
+            Some(reading) if self.return_last_while_busy => Ok(reading),
+            _ => Err(Error::WouldBlock),
+        }
+    }
+
+    /// Forget the measurements of a partially completed oversampled reading
+    fn discard_burst(&mut self) {
+        self.burst_sum = 0;
+        self.burst_len = 0;
+        self.burst_ticks = (0, 0);
+    }
+
+    /// Note the end of a measurement, successful or not
+    fn finish(&mut self) {
+        if let Some(start) = self.triggered_at.take() {
+            self.active_ticks += self.elapsed(start) as u64;
+        }
+    }
+
+    /// Note the rising edge of the return pulse, returning the new mode
+    fn pulse_start(&mut self) -> Mode {
+        let now = self.timer.now();
+        self.last_edge = now;
+        self.odd_ignored_edges = false;
+        Mode::MeasurePulse(now)
+    }
+
+    /// Ticks of the `Clock` since `since`
+    fn elapsed(&self, since: u32) -> u32 {
+        self.timer.now().wrapping_sub(since)
+    }
+
+    /// Minimum width of the return pulse in ticks of a timer running at `hz`
+    fn debounce_ticks(&self, hz: u32) -> u32 {
+        (self.debounce_us as u64 * hz as u64 / 1_000_000) as u32
+    }
+
+    /// Convert the width of a return pulse, in ticks of a timer running at
+    /// `hz`, into the resulting mode
+    fn measurement(&mut self, ticks: u32, hz: u32) -> Mode {
+        if let Some(start) = self.triggered_at {
+            let timer_hz = self.timer.frequency().max(1) as u64;
+            let latency_us = self.elapsed(start) as u64 * 1_000_000 / timer_hz;
+            self.last_latency_us = Some(saturate(latency_us));
+        }
+        self.finish();
+        self.pulse_ended = true;
+        let result = self.convert(ticks, hz);
+        self.last_result = Some(result);
+        if let (Ok(dist), Some(on_complete)) = (result, self.on_complete) {
+            on_complete(dist);
+        }
+        match result {
+            Ok(dist) => Mode::Measurement(dist),
+            Err(err) => Mode::Failed(err),
+        }
+    }
+
+    /// Convert the width of a return pulse, in ticks of a timer running at
+    /// `hz`, into a distance
+    fn convert(&mut self, ticks: u32, hz: u32) -> Result<Distance, SensorError> {
+        self.last_ticks = Some((ticks, hz));
+        // Keep temperature compensation current
+        if let Some(read_temp) = self.temperature_fn {
+            self.set_temperature(read_temp());
+        }
+        // Far longer than the sensor produces even when nothing reflects the
+        // pulse, the timestamps or frequency can't be trusted
+        if ticks as u64 > ECHO_STUCK_MS as u64 * hz as u64 / 1_000 {
+            return Err(SensorError::ImplausiblePulse { ticks: ticks });
+        }
+        // The sensor signals that the pulse was not reflected with a
+        // pulse of maximum length
+        if ticks as u64 >= NO_OBJECT_US as u64 * hz as u64 / 1_000_000 {
+            return Err(SensorError::NoObjectDetected { ticks: ticks });
+        }
+        // Remove the asymmetry between the edges so that the checks below
+        // see the actual width of the pulse
+        let offset_ticks = self.width_offset_ns as u64 * hz as u64 / 1_000_000_000;
+        let corrected = ticks.saturating_sub(offset_ticks as u32);
+        // Pulses outside of what the sensor produces for objects within
+        // range are either noise or a fault
+        let (min_us, max_us) = self.pulse_limits;
+        let min_ticks = min_us as u64 * hz as u64 / 1_000_000;
+        let max_ticks = max_us as u64 * hz as u64 / 1_000_000;
+        if (corrected as u64) < min_ticks || corrected as u64 > max_ticks {
+            return Err(SensorError::OutOfSpec { ticks: ticks });
+        }
+        // A pulse longer than what the maximum distance can produce means
+        // the timestamps or frequency can't be trusted
+        if let Some(max) = self.max_distance {
+            let max_ticks = max.0 as u64 * hz as u64 / self.speed_factor() as u64;
+            if corrected as u64 > max_ticks {
+                return Err(SensorError::ImplausiblePulse { ticks: ticks });
+            }
+        }
+        // Calculation is `distance = seconds * 343.21 m/s * 0.5`, with the
+        // speed factor and frequency folded into a single constant this is
+        // one multiplication. The no object check above bounds `ticks` to
+        // 30 ms worth of ticks so the product can't overflow.
+        let constant = self.conversion_constant(hz);
+        let distance_mm = ticks_to_mm(corrected, self.speed_factor(), hz, constant);
+        let dist = Distance(saturate(distance_mm));
+        self.last_computation = Some(Computation {
+            ticks: ticks,
+            hz: hz,
+            echo_us: (ticks as u64 * 1_000_000 / hz.max(1) as u64) as u32,
+            offset_ticks: offset_ticks as u32,
+            speed_factor: self.speed_factor(),
+            distance_mm: dist.0,
+        });
+        match self.reading_filter {
+            Some(accept) if !accept(dist) => Err(SensorError::Rejected { ticks: ticks }),
+            _ => Ok(dist),
+        }
+    }
+
+    /// Millimeters per tick of a timer running at `hz` as Q32.32, computed
+    /// only when the speed factor or frequency has changed
+    fn conversion_constant(&mut self, hz: u32) -> u64 {
+        let factor = self.speed_factor();
+        match self.conversion {
+            Some((cached_factor, cached_hz, constant))
+                if cached_factor == factor && cached_hz == hz =>
+            {
+                constant
+            }
+            _ => {
+                let constant = mm_per_tick_q32(factor, hz);
+                self.conversion = Some((factor, hz, constant));
+                constant
+            }
+        }
+    }
+
+    /// Complete a measurement from the given edge timestamps.
+    ///
+    /// This skips the trigger and interrupts entirely and converts the pulse
+    /// from `rising` to `falling`, in ticks of the `Clock`, as if it had been
+    /// measured. The next call to `distance` returns the result, which makes
+    /// the conversion directly testable. Only available with the `testing`
+    /// feature.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn debug_preload(&mut self, rising: u32, falling: u32) {
+        let hz = self.timer.frequency();
+        self.mode = self.measurement(falling.wrapping_sub(rising), hz);
+    }
+
+    /// Trigger sensor starting a measurement
+    fn trigger(&mut self) {
+        if self.settle_us > 0 {
+            self.delay.delay_us(self.settle_us);
+        }
+        self.multiple_echoes = false;
+        self.pulse_ended = false;
+        let start = self.timer.now();
+        self.triggered_at = Some(start);
+        self.cycle_start = Some(start);
+        self.pin.set_high();
+        self.delay.delay_us(consts::TRIGGER_WIDTH_US);
+        self.pin.set_low();
+        // Record how long the trigger pulse actually lasted, this includes
+        // the time spent toggling the pin
+        let width = self.elapsed(start);
+        self.trigger_widths = Some(match self.trigger_widths {
+            Some((min, max)) => (min.min(width), max.max(width)),
+            None => (width, width),
+        });
+        self.timeout.start(self.timeout_period);
+        self.mode = Mode::Triggered;
+    }
+}
+
+impl<Pin, Delay, Timeout, Clk, EchoPin, Filt> HcSr04<Pin, Delay, Timeout, Clk, EchoPin, Filt>
+where
+    Pin: OutputPin,
+    Delay: DelayUs<u32>,
+    Timeout: CountDown,
+    Timeout::Time: Copy,
+    Clk: Clock,
+    EchoPin: InputPin,
+    Filt: Filter,
+{
+    /// Update the internal state in response to an interrupt, reading the
+    /// level of the echo pin owned by the driver.
+    ///
+    /// The methods of this block are available when the driver owns the
+    /// echo pin, see `HcSr04Builder::echo`. Each is the same as the method
+    /// taking the pin as argument:
+    ///
+    /// - `update_echo` is `update_from_pin`.
+    /// - `echo_noise_floor` is `measure_noise_floor`.
+    /// - `measure_echo` is `measure_with_delay`.
+    /// - `measure_echo_samples` is `measure_samples`.
+    /// - `echo_connected` is `is_connected`.
+    ///
+    /// In addition `measure_snr` estimates the quality of the readings.
+    pub fn update_echo(&mut self) -> Result<(), SensorError> {
+        self.with_echo(|sensor, echo| sensor.update_from_pin(echo))
+    }
+
+    /// Count spurious edges on the owned echo pin, see
+    /// `measure_noise_floor`.
+    pub fn echo_noise_floor(&mut self, samples: u16) -> u16 {
+        self.with_echo(|sensor, echo| sensor.measure_noise_floor(echo, samples))
+    }
+
+    /// Take a blocking measurement sampling the owned echo pin, see
+    /// `measure_with_delay`.
+    pub fn measure_echo(&mut self, step_us: u32) -> Result<Distance, SensorError> {
+        self.with_echo(|sensor, echo| sensor.measure_with_delay(echo, step_us))
+    }
+
+    /// Take `n` blocking measurements sampling the owned echo pin, see
+    /// `measure_samples`.
+    pub fn measure_echo_samples(&mut self, step_us: u32, n: u8, out: &mut [Distance]) -> usize {
+        self.with_echo(|sensor, echo| sensor.measure_samples(echo, step_us, n, out))
+    }
+
+    /// Check if a sensor responds on the owned echo pin, see
+    /// `is_connected`.
+    pub fn echo_connected(&mut self) -> bool {
+        self.with_echo(|sensor, echo| sensor.is_connected(echo))
+    }
+
+    /// Estimate the signal-to-noise ratio of `n` blocking measurements.
+    ///
+    /// The sensor is pinged `n` times in a row with `measure_echo`, sampling
+    /// the echo pin every 10 µs and waiting `consts::MEASUREMENT_CYCLE_US`
+    /// between pings, so this blocks for roughly `n * 60` ms. The ratio is
+    /// computed from the successful measurements as for `stats::snr`, i.e.
+    /// the mean distance divided by its standard deviation. Point the sensor
+    /// at a fixed target, a low ratio then indicates a poor target or
+    /// interference.
+    ///
+    /// # Return
+    /// The ratio, `u16::MAX` if all measurements were equal and `0` if none
+    /// succeeded.
+    pub fn measure_snr(&mut self, n: u8) -> u16 {
+        let (mut count, mut sum, mut sum_sq) = (0u64, 0u64, 0u64);
+        for i in 0..n {
+            if i > 0 {
+                self.delay.delay_us(consts::MEASUREMENT_CYCLE_US);
+            }
+            if let Ok(dist) = self.measure_echo(10) {
+                let mm = dist.mm() as u64;
+                count += 1;
+                sum = sum.saturating_add(mm);
+                sum_sq = sum_sq.saturating_add(mm * mm);
+            }
+        }
+        if count == 0 {
+            return 0;
+        }
+        // Variance as the mean of squares minus the squared mean
+        let variance = sum_sq.saturating_sub(sum.saturating_mul(sum) / count) / count;
+        stats::snr_from_moments(sum / count, variance)
+    }
+
+    /// Borrow the echo pin owned by the driver.
+    pub fn echo(&self) -> &EchoPin {
+        // Only `with_echo` takes the pin out, and it always puts it back
+        self.echo.as_ref().expect("echo pin taken")
+    }
+
+    /// Call `f` with the driver and the echo pin it owns
+    fn with_echo<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut Self, &EchoPin) -> R,
+    {
+        // Take the pin out for the duration of the call so that it can be
+        // borrowed alongside the driver
+        let echo = self.echo.take().expect("echo pin taken");
+        let result = f(self, &echo);
+        self.echo = Some(echo);
+        result
+    }
+}
+
+impl<Pin, Delay, Timeout, Clk, EchoPin, Filt> OneShot
+    for HcSr04<Pin, Delay, Timeout, Clk, EchoPin, Filt>
+where
+    Pin: OutputPin,
+    Delay: DelayUs<u32>,
+    Timeout: CountDown,
+    Timeout::Time: Copy,
+    Clk: Clock,
+    Filt: Filter,
+{
+    type Word = Distance;
+    type Error = SensorError;
+
+    fn read(&mut self) -> nb::Result<Distance, SensorError> {
+        self.distance()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock;
+
+    /// Check the fixed point conversion against the exact division for every
+    /// pulse shorter than the no object threshold
+    fn check_conversion(factor: u32, hz: u32) {
+        let constant = mm_per_tick_q32(factor, hz);
+        let max_ticks = (NO_OBJECT_US as u64 * hz as u64 / 1_000_000) as u32;
+        for ticks in 0..max_ticks {
+            let exact = ticks as u64 * factor as u64 / hz as u64;
+            assert_eq!(ticks_to_mm(ticks, factor, hz, constant), exact);
+        }
+    }
+
+    #[test]
+    fn conversion_low_frequency() {
+        check_conversion(DEFAULT_SPEED_FACTOR, 999);
+        check_conversion(DEFAULT_SPEED_FACTOR * 2, 999);
+    }
+
+    #[test]
+    fn conversion_8mhz() {
+        check_conversion(DEFAULT_SPEED_FACTOR, 8_000_000);
+        check_conversion(DEFAULT_SPEED_FACTOR * 2, 8_000_000);
+    }
+
+    #[test]
+    fn conversion_prime_frequency() {
+        check_conversion(DEFAULT_SPEED_FACTOR, 1_000_003);
+        check_conversion(DEFAULT_SPEED_FACTOR * 2, 1_000_003);
+    }
+
+    #[test]
+    fn conversion_zero_frequency() {
+        assert_eq!(mm_per_tick_q32(DEFAULT_SPEED_FACTOR, 0), (DEFAULT_SPEED_FACTOR as u64) << 32);
+    }
+
+    /// Measure a return pulse of `ticks` captured at the capture frequency
+    fn measure(sensor: &mut mock::MockSensor, ticks: u32) -> u32 {
+        sensor.fire().unwrap();
+        sensor.capture_pair(100, 100 + ticks).unwrap();
+        sensor.distance().unwrap().mm()
+    }
+
+    #[test]
+    fn setters_invalidate_conversion() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        let base = measure(&mut sensor, 5_800);
+        assert_eq!(base as u64, 5_800 * DEFAULT_SPEED_FACTOR as u64 / 1_000_000);
+
+        sensor.set_temperature(40);
+        let warm = measure(&mut sensor, 5_800);
+        assert!(warm > base);
+        assert_eq!(warm as u64, 5_800 * sensor.speed_factor() as u64 / 1_000_000);
+        assert_eq!(sensor.conversion.map(|c| c.0), Some(sensor.speed_factor()));
+
+        sensor.set_one_way(true);
+        let one_way = measure(&mut sensor, 5_800);
+        assert_eq!(one_way as u64, 5_800 * sensor.speed_factor() as u64 / 1_000_000);
+        assert_eq!(sensor.conversion.map(|c| c.0), Some(sensor.speed_factor()));
+
+        sensor.set_capture_frequency(Hertz(2_000_000));
+        let fast = measure(&mut sensor, 5_800);
+        assert_eq!(fast as u64, 5_800 * sensor.speed_factor() as u64 / 2_000_000);
+        assert_eq!(sensor.conversion.map(|c| c.1), Some(2_000_000));
+    }
+
+    #[test]
+    fn latency_from_trigger_to_falling_edge() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        assert_eq!(sensor.last_latency_us(), None);
+        // The trigger pulse advances the clock by its width
+        sensor.fire().unwrap();
+        clock.advance(500);
+        sensor.update().unwrap();
+        clock.advance(5_800);
+        sensor.update().unwrap();
+        assert_eq!(sensor.last_latency_us(), Some(consts::TRIGGER_WIDTH_US + 500 + 5_800));
+        assert!(sensor.distance().is_ok());
+
+        // A timed out measurement keeps the previous latency
+        sensor.fire().unwrap();
+        clock.advance(1_000);
+        sensor.timedout();
+        assert_eq!(sensor.last_latency_us(), Some(consts::TRIGGER_WIDTH_US + 500 + 5_800));
+    }
+
+    #[test]
+    fn latency_at_other_frequency() {
+        let clock = MockClock::new(8_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.fire().unwrap();
+        sensor.update().unwrap();
+        clock.advance(8_000);
+        sensor.update().unwrap();
+        assert_eq!(sensor.last_latency_us(), Some(consts::TRIGGER_WIDTH_US + 1_000));
+    }
+
+    /// A driver state of each kind with the state code it reports
+    fn states() -> [(Mode, u8); 6] {
+        [
+            (Mode::Idle, 0),
+            (Mode::Triggered, 1),
+            (Mode::MeasurePulse(0), 2),
+            (Mode::CapturePulse(0), 3),
+            (Mode::Measurement(Distance(995)), 4),
+            (Mode::Failed(SensorError::OutOfSpec { ticks: 50 }), 5),
+        ]
+    }
+
+    /// Apply `event` 6 ms into the state to a driver in each of `states`,
+    /// checking whether it succeeded and the state it left the driver in
+    fn check_event<F>(event: F, expected: [(bool, u8); 6])
+    where
+        F: Fn(&mut mock::MockSensor) -> bool,
+    {
+        for (&(mode, code), &(ok, next)) in states().iter().zip(expected.iter()) {
+            let clock = MockClock::new(1_000_000);
+            let mut sensor = mock::sensor(&clock);
+            sensor.mode = mode;
+            clock.set(6_000);
+            assert_eq!(event(&mut sensor), ok, "result in state {}", code);
+            assert_eq!(sensor.state_code(), next, "state after state {}", code);
+        }
+    }
+
+    #[test]
+    fn distance_in_each_state() {
+        check_event(
+            |sensor| sensor.distance().is_ok(),
+            [(false, 1), (false, 1), (false, 2), (false, 3), (true, 0), (false, 0)],
+        );
+    }
+
+    #[test]
+    fn fire_in_each_state() {
+        check_event(
+            |sensor| sensor.fire().is_ok(),
+            [(true, 1), (false, 1), (false, 2), (false, 3), (false, 4), (false, 5)],
+        );
+    }
+
+    #[test]
+    fn restart_in_each_state() {
+        check_event(|sensor| sensor.restart().is_ok(), [(true, 1); 6]);
+    }
+
+    #[test]
+    fn timedout_in_each_state() {
+        check_event(
+            |sensor| {
+                sensor.timedout();
+                true
+            },
+            [(true, 0); 6],
+        );
+    }
+
+    #[test]
+    fn update_in_each_state() {
+        check_event(
+            |sensor| sensor.update().is_ok(),
+            [(false, 0), (true, 2), (true, 4), (false, 3), (false, 4), (false, 5)],
+        );
+    }
+
+    #[test]
+    fn update_from_pin_in_each_state() {
+        check_event(
+            |sensor| sensor.update_from_pin(&mock::MockEcho { high: true }).is_ok(),
+            [(false, 0), (true, 2), (true, 2), (false, 3), (false, 4), (false, 5)],
+        );
+        check_event(
+            |sensor| sensor.update_from_pin(&mock::MockEcho { high: false }).is_ok(),
+            [(false, 0), (true, 1), (true, 4), (false, 3), (false, 4), (false, 5)],
+        );
+    }
+
+    #[test]
+    fn poll_echo_in_each_state() {
+        check_event(
+            |sensor| sensor.poll_echo(true).is_ok(),
+            [(true, 0), (true, 2), (true, 2), (true, 3), (true, 4), (true, 5)],
+        );
+        check_event(
+            |sensor| sensor.poll_echo(false).is_ok(),
+            [(true, 0), (true, 1), (true, 4), (true, 3), (true, 4), (true, 5)],
+        );
+    }
+
+    #[test]
+    fn capture_pair_in_each_state() {
+        check_event(
+            |sensor| sensor.capture_pair(0, 5_800).is_ok(),
+            [(false, 0), (true, 4), (false, 2), (false, 3), (false, 4), (false, 5)],
+        );
+        check_event(
+            |sensor| sensor.capture_from_buffer(&[0, 5_800]).is_ok(),
+            [(false, 0), (true, 4), (false, 2), (false, 3), (false, 4), (false, 5)],
+        );
+    }
+
+    #[test]
+    fn capture_with_period_in_each_state() {
+        check_event(
+            |sensor| sensor.capture_with_period(5_800, u16::max_value()).is_ok(),
+            [(false, 0), (true, 3), (false, 2), (true, 4), (false, 4), (false, 5)],
+        );
+    }
+
+    #[test]
+    fn distance_triggers_when_idle() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        assert_eq!(sensor.state_code(), 0);
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        assert_eq!(sensor.state_code(), 1);
+        assert!(sensor.pin.is_low());
+        // Polling again does not trigger again
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        assert_eq!(clock.now(), consts::TRIGGER_WIDTH_US);
+    }
+
+    #[test]
+    fn full_measurement_cycle() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.fire().unwrap();
+        clock.advance(500);
+        sensor.update().unwrap();
+        assert_eq!(sensor.state_code(), 2);
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        clock.advance(5_800);
+        sensor.update().unwrap();
+        assert_eq!(sensor.state_code(), 4);
+        assert_eq!(sensor.peek_distance().map(|d| d.mm()), Some(995));
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+        assert_eq!(sensor.state_code(), 0);
+        assert_eq!(sensor.last_delta_ticks(), Some(5_800));
+        assert!(sensor.last_reading_valid());
+    }
+
+    #[test]
+    fn manual_trigger() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_manual_trigger(true);
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        assert_eq!(sensor.state_code(), 0);
+        assert_eq!(clock.now(), 0);
+        assert_eq!(sensor.start_measurement(), MeasurementStart::Started);
+        assert_eq!(sensor.start_measurement(), MeasurementStart::AlreadyBusy);
+        sensor.capture_pair(0, 5_800).unwrap();
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        assert_eq!(sensor.state_code(), 0);
+    }
+
+    #[test]
+    fn return_last_while_busy() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_return_last_while_busy(true);
+        // Nothing to return before the first reading
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        sensor.capture_pair(0, 5_800).unwrap();
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+        // Triggers the next measurement and returns the previous reading
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+        assert_eq!(sensor.state_code(), 1);
+        // Errors are returned as they occur
+        sensor.timedout();
+        sensor.debug_preload(0, 50);
+        assert!(matches!(
+            sensor.distance(),
+            Err(Error::Other(SensorError::OutOfSpec { ticks: 50 }))
+        ));
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+    }
+
+    #[test]
+    fn restart_abandons_measurement() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.fire().unwrap();
+        sensor.update().unwrap();
+        sensor.restart().unwrap();
+        assert_eq!(sensor.state_code(), 1);
+        sensor.capture_pair(0, 5_800).unwrap();
+        assert!(sensor.restart().is_ok());
+        assert_eq!(sensor.peek_distance(), None);
+        sensor.inhibit();
+        assert!(matches!(sensor.restart(), Err(SensorError::Inhibited)));
+        assert_eq!(sensor.state_code(), 1);
+    }
+
+    #[test]
+    fn update_pending_sensors() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensors = [mock::sensor(&clock), mock::sensor(&clock), mock::sensor(&clock)];
+        sensors[0].fire().unwrap();
+        sensors[2].fire().unwrap();
+        // The idle sensor fails, bits beyond the sensors are ignored
+        assert_eq!(HcSr04::update_pending(&mut sensors, 0b1011), 0b10);
+        assert_eq!(sensors[0].state_code(), 2);
+        assert_eq!(sensors[1].state_code(), 0);
+        assert_eq!(sensors[2].state_code(), 1);
+        clock.advance(5_800);
+        assert_eq!(HcSr04::update_pending(&mut sensors, 0b1), 0);
+        assert_eq!(sensors[0].distance().unwrap().mm(), 995);
+    }
+
+    #[test]
+    fn on_complete_called_for_each_measurement() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        static LAST_MM: AtomicUsize = AtomicUsize::new(0);
+        fn record(distance: Distance) {
+            LAST_MM.store(distance.mm() as usize, Ordering::SeqCst);
+        }
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_on_complete(Some(record));
+        sensor.fire().unwrap();
+        sensor.capture_pair(0, 5_800).unwrap();
+        // Called before the reading is polled
+        assert_eq!(LAST_MM.load(Ordering::SeqCst), 995);
+        sensor.distance().unwrap();
+        // Not called for failed measurements
+        sensor.debug_preload(0, 50);
+        assert_eq!(LAST_MM.load(Ordering::SeqCst), 995);
+        sensor.set_on_complete(None);
+        sensor.debug_preload(0, 11_600);
+        assert_eq!(LAST_MM.load(Ordering::SeqCst), 995);
+    }
+
+    #[test]
+    fn preload_converts_pulse() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.debug_preload(1_000, 1_000 + 11_600);
+        assert_eq!(sensor.distance().unwrap().mm(), 1_990);
+        // Timestamps wrapping around are handled
+        sensor.debug_preload(u32::max_value() - 99, 5_700);
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+    }
+
+    #[test]
+    fn pulse_out_of_spec() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.debug_preload(0, 50);
+        assert_eq!(sensor.state_code(), 5);
+        assert!(matches!(
+            sensor.distance(),
+            Err(Error::Other(SensorError::OutOfSpec { ticks: 50 }))
+        ));
+        assert_eq!(sensor.state_code(), 0);
+        assert!(!sensor.last_reading_valid());
+    }
+
+    #[test]
+    fn echo_stuck() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.fire().unwrap();
+        sensor.update().unwrap();
+        clock.advance(ECHO_STUCK_MS * 1_000);
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        clock.advance(1);
+        assert!(matches!(
+            sensor.distance(),
+            Err(Error::Other(SensorError::EchoStuck { .. }))
+        ));
+        assert_eq!(sensor.state_code(), 0);
+    }
+
+    #[test]
+    fn timeout_abandons_measurement() {
+        let clock = MockClock::new(1_000_000);
+        let expired = Cell::new(false);
+        let mut sensor = HcSr04::with_timeout(
+            mock::MockPin { high: false },
+            mock::MockDelay { clock: &clock },
+            &clock,
+            mock::MockTimeout { expired: &expired },
+            (),
+        );
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        sensor.update().unwrap();
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        assert_eq!(sensor.state_code(), 2);
+        expired.set(true);
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        assert_eq!(sensor.state_code(), 0);
+        // The next poll starts over, restarting the timeout
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        assert_eq!(sensor.state_code(), 1);
+        assert!(!expired.get());
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.fire().unwrap();
+        sensor.update().unwrap();
+        let snapshot = sensor.debug_snapshot();
+        let taken_at = clock.now();
+        clock.advance(5_800);
+        sensor.update().unwrap();
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+
+        sensor.debug_restore(snapshot);
+        assert_eq!(sensor.state_code(), 2);
+        clock.set(taken_at);
+        clock.advance(11_600);
+        sensor.update().unwrap();
+        assert_eq!(sensor.distance().unwrap().mm(), 1_990);
+    }
+
+    #[test]
+    fn inhibited_sensor_is_not_triggered() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.inhibit();
+        assert!(matches!(sensor.distance(), Err(Error::Other(SensorError::Inhibited))));
+        assert!(matches!(sensor.fire(), Err(SensorError::Inhibited)));
+        assert_eq!(sensor.state_code(), 0);
+        sensor.allow();
+        assert_eq!(sensor.start_measurement(), MeasurementStart::Started);
+        assert_eq!(sensor.start_measurement(), MeasurementStart::AlreadyBusy);
+    }
+
+    #[test]
+    fn glitch_pair_is_ignored() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.fire().unwrap();
+        sensor.update().unwrap();
+        // Falling and rising edge of a glitch, each within the debounce
+        // time of the previous edge
+        clock.advance(50);
+        sensor.update().unwrap();
+        clock.advance(60);
+        sensor.update().unwrap();
+        assert_eq!(sensor.state_code(), 2);
+        clock.advance(5_690);
+        sensor.update().unwrap();
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+    }
+
+    #[test]
+    fn noise_spike_before_pulse_is_ignored() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.fire().unwrap();
+        // A spike on the echo line, its falling edge is ignored leaving the
+        // line low
+        sensor.update().unwrap();
+        clock.advance(20);
+        sensor.update().unwrap();
+        // The rising edge of the real return pulse
+        clock.advance(480);
+        sensor.update().unwrap();
+        clock.advance(11_600);
+        sensor.update().unwrap();
+        assert_eq!(sensor.distance().unwrap().mm(), 1_990);
+    }
+
+    #[test]
+    fn late_edge_after_timeout_is_not_an_echo() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_detect_multiple_echoes(true);
+        sensor.fire().unwrap();
+        sensor.update().unwrap();
+        sensor.timedout();
+        assert!(matches!(sensor.update(), Err(SensorError::WrongMode)));
+        assert!(!sensor.multiple_echoes());
+
+        sensor.fire().unwrap();
+        sensor.update().unwrap();
+        clock.advance(5_800);
+        sensor.update().unwrap();
+        // Rising edge of a second echo
+        clock.advance(500);
+        sensor.update().unwrap();
+        assert!(sensor.multiple_echoes());
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+    }
+
+    #[test]
+    fn capture_pair_validates_timestamps() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_counter_period(Some(10_000));
+        sensor.fire().unwrap();
+        assert!(matches!(
+            sensor.capture_pair(10_000, 100),
+            Err(SensorError::InvalidTimestamp { timestamp: 10_000 })
+        ));
+        assert_eq!(sensor.state_code(), 1);
+        // Wrapped counter
+        sensor.capture_pair(9_000, 4_800).unwrap();
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+
+        // Out of order timestamps can't be explained by a 32-bit counter
+        // wrapping during a pulse
+        sensor.set_counter_period(None);
+        sensor.fire().unwrap();
+        assert!(matches!(
+            sensor.capture_pair(10_000, 100),
+            Err(SensorError::ImplausiblePulse { .. })
+        ));
+        assert_eq!(sensor.state_code(), 1);
+        sensor.capture_pair(u32::max_value() - 799, 5_000).unwrap();
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+    }
+
+    #[test]
+    fn width_offset_applies_before_validation() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_width_offset_ns(100_000);
+        sensor.debug_preload(0, 200);
+        assert!(matches!(
+            sensor.distance(),
+            Err(Error::Other(SensorError::OutOfSpec { ticks: 200 }))
+        ));
+        sensor.debug_preload(0, 5_900);
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+        assert_eq!(sensor.last_computation().map(|c| c.offset_ticks), Some(100));
+    }
+
+    #[test]
+    fn reset_calibration_restores_defaults() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_speed_table(Some(&SPEED_FACTOR_TABLE));
+        sensor.set_temperature(-10);
+        sensor.set_one_way(true);
+        sensor.set_width_offset_ns(1_000);
+        sensor.reset_calibration();
+        assert_eq!(sensor.temperature(), None);
+        assert_eq!(sensor.speed_factor(), speed_factor_at(DEFAULT_TEMPERATURE));
+        assert!(sensor.speed_table.is_none());
+        assert_eq!(sensor.config().width_offset_ns, 0);
+    }
+
+    #[test]
+    fn is_connected_keeps_last_result() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.debug_preload(0, 5_800);
+        assert!(sensor.distance().is_ok());
+        assert!(sensor.is_connected(&mock::MockEcho { high: true }));
+        assert!(!sensor.is_connected(&mock::MockEcho { high: false }));
+        assert_eq!(sensor.peek_distance().map(|d| d.mm()), Some(995));
+        assert!(sensor.last_reading_valid());
+        assert_eq!(sensor.state_code(), 0);
+    }
+
+    #[test]
+    fn timing_health_uses_frequency_of_pulses() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_capture_frequency(Hertz(2_000_000));
+        sensor.set_oversampling(2);
+        assert_eq!(sensor.timing_health(20), TimingHealth::Unknown);
+        sensor.fire().unwrap();
+        sensor.capture_pair(0, 11_600).unwrap();
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        sensor.capture_pair(0, 11_700).unwrap();
+        assert!(sensor.distance().is_ok());
+        // Standard deviation of 50 ticks, i.e. 25 µs at 2 MHz
+        assert_eq!(sensor.pulse_width_variance(), Some(2_500));
+        assert_eq!(sensor.timing_health(30), TimingHealth::Stable);
+        assert_eq!(sensor.timing_health(20), TimingHealth::Unstable);
+    }
+
+    #[test]
+    fn builder_filter() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = builder::HcSr04Builder::new(
+            mock::MockPin { high: false },
+            mock::MockDelay { clock: &clock },
+            &clock,
+        ).filter(filter::DeadBand::new(10))
+            .build()
+            .ok()
+            .unwrap();
+        sensor.debug_preload(0, 5_800);
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+        // Within the dead band, the raw measurement is still available
+        sensor.debug_preload(0, 5_830);
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+        assert_eq!(sensor.peek_distance().map(|d| d.mm()), Some(1_000));
+        sensor.filter_mut().reset();
+        sensor.debug_preload(0, 5_830);
+        assert_eq!(sensor.distance().unwrap().mm(), 1_000);
+    }
+
+    #[test]
+    fn builder_echo() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = builder::HcSr04Builder::new(
+            mock::MockPin { high: false },
+            mock::MockDelay { clock: &clock },
+            &clock,
+        ).echo(mock::MockEcho { high: true })
+            .build()
+            .ok()
+            .unwrap();
+        assert!(sensor.echo_connected());
+        sensor.fire().unwrap();
+        sensor.update_echo().unwrap();
+        assert_eq!(sensor.state_code(), 2);
+    }
+
+    #[test]
+    fn distance_from_echo_us() {
+        assert_eq!(Distance::from_echo_us(5_800, DEFAULT_SPEED_FACTOR).mm(), 995);
+        assert_eq!(Distance::from_echo_us(0, DEFAULT_SPEED_FACTOR).mm(), 0);
+        let max = u32::max_value();
+        assert_eq!(Distance::from_echo_us(max, max).mm(), max);
+    }
+
+    #[test]
+    fn distance_from_ticks() {
+        let dist = Distance::from_ticks(1_000, 12_600, 2_000_000, DEFAULT_SPEED_FACTOR);
+        assert_eq!(dist.mm(), 995);
+        // The counter wrapping between the edges
+        let max = u32::max_value();
+        let dist = Distance::from_ticks(max - 99, 5_700, 1_000_000, DEFAULT_SPEED_FACTOR);
+        assert_eq!(dist.mm(), 995);
+        assert_eq!(Distance::from_ticks(0, 5_800, 0, DEFAULT_SPEED_FACTOR).mm(), 0);
+    }
+
+    #[test]
+    fn distance_tenths_inch() {
+        assert_eq!(Distance(1_234).tenths_inch(), 485);
+        // 100 inches is exactly 2540 mm
+        assert_eq!(Distance(2_539).tenths_inch(), 999);
+        assert_eq!(Distance(2_540).tenths_inch(), 1_000);
+        assert_eq!(Distance(0).tenths_inch(), 0);
+    }
+
+    #[test]
+    fn distance_in_unit() {
+        let dist = Distance(1_234);
+        assert_eq!(dist.in_unit(Unit::Mm), 1_234);
+        assert_eq!(dist.in_unit(Unit::Cm), 123);
+        assert_eq!(dist.in_unit(Unit::Inch), 48);
+        assert_eq!(dist.in_unit(Unit::Foot), 4);
+    }
+
+    #[test]
+    fn distance_as_all() {
+        assert_eq!(
+            Distance(1_234).as_all(),
+            Measurements {
+                mm: 1_234,
+                cm: 123,
+                inches: 48,
+            }
+        );
+    }
+
+    #[test]
+    fn distance_scaled() {
+        let dist = Distance(1_234);
+        assert_eq!(dist.scaled(0), 1_234);
+        assert_eq!(dist.scaled(2), 123_400);
+        assert_eq!(dist.scaled(10), u32::max_value());
+    }
+
+    #[test]
+    fn distance_meters_whole_and_mm() {
+        assert_eq!(Distance(1_234).meters_whole_and_mm(), (1, 234));
+        assert_eq!(Distance(999).meters_whole_and_mm(), (0, 999));
+        assert_eq!(Distance(1_000).meters_whole_and_mm(), (1, 0));
+        assert_eq!(Distance(1_999).meters_whole_and_mm(), (1, 999));
+    }
+
+    #[test]
+    fn distance_value_and_scale() {
+        assert_eq!(Distance(1_234).value_and_scale(), (1_234, -3));
+        assert_eq!(Distance(237).value_and_scale(), (237, -3));
+    }
+
+    #[test]
+    fn distance_meters_q16() {
+        assert_eq!(Distance(1_500).meters_q16(), 0x1_8000);
+        assert_eq!(Distance(1_000).meters_q16(), 0x1_0000);
+        // Converting back to millimeters with rounding gives the distance
+        for mm in 0..10_000 {
+            let q16 = Distance(mm).meters_q16() as u64;
+            assert_eq!((q16 * 1_000 + 0x8000) >> 16, mm as u64);
+        }
+    }
+
+    #[test]
+    fn distance_signed_cm() {
+        assert_eq!(Distance(1_234).cm_i32(), 123);
+        let near = Distance(1_000);
+        let far = Distance(1_234);
+        assert_eq!(near.cm_relative_to(far), -23);
+        assert_eq!(far.cm_relative_to(near), 23);
+        assert_eq!(near.cm_relative_to(near), 0);
+    }
+
+    #[test]
+    fn distance_rounding() {
+        let tie_odd = Distance(1_235);
+        assert_eq!(tie_odd.cm_rounded(RoundingMode::Truncate), 123);
+        assert_eq!(tie_odd.cm_rounded(RoundingMode::HalfUp), 124);
+        assert_eq!(tie_odd.cm_rounded(RoundingMode::HalfEven), 124);
+        let tie_even = Distance(1_245);
+        assert_eq!(tie_even.cm_rounded(RoundingMode::HalfUp), 125);
+        assert_eq!(tie_even.cm_rounded(RoundingMode::HalfEven), 124);
+        let dist = Distance(1_234);
+        assert_eq!(dist.in_unit_rounded(Unit::Inch, RoundingMode::Truncate), 48);
+        assert_eq!(dist.in_unit_rounded(Unit::Inch, RoundingMode::HalfUp), 49);
+        assert_eq!(dist.in_unit_rounded(Unit::Mm, RoundingMode::HalfEven), 1_234);
+    }
+
+    #[test]
+    fn distance_error_from() {
+        let near = Distance(1_000);
+        let far = Distance(1_234);
+        assert_eq!(near.error_from(far), -234);
+        assert_eq!(far.error_from(near), 234);
+        assert_eq!(near.error_from(near), 0);
+        assert_eq!(Distance(u32::max_value()).error_from(Distance(0)), i32::max_value());
+    }
+
+    #[test]
+    fn distance_approx_eq() {
+        let dist = Distance(1_000);
+        assert!(dist.approx_eq(Distance(1_005), 5));
+        assert!(dist.approx_eq(Distance(995), 5));
+        assert!(!dist.approx_eq(Distance(994), 5));
+        assert!(dist.approx_eq(dist, 0));
+    }
+
+    #[test]
+    fn distance_ratio_to() {
+        let dist = Distance(1_000);
+        assert_eq!(Distance(500).ratio_to(dist), 500);
+        assert_eq!(dist.ratio_to(dist), 1_000);
+        assert_eq!(Distance(0).ratio_to(Distance(0)), 1_000);
+        assert_eq!(dist.ratio_to(Distance(0)), u16::max_value());
+        assert_eq!(dist.ratio_to(Distance(1)), u16::max_value());
+    }
+
+    #[test]
+    fn distance_to_duty() {
+        assert_eq!(Distance(600).to_duty(10, 110, 1_000), 500);
+        assert_eq!(Distance(50).to_duty(10, 110, 1_000), 0);
+        assert_eq!(Distance(2_000).to_duty(10, 110, 1_000), 1_000);
+    }
+
+    #[test]
+    fn distance_percent_of_range() {
+        assert_eq!(Distance(600).percent_of_range(10, 110), 50);
+        assert_eq!(Distance(50).percent_of_range(10, 110), 0);
+        assert_eq!(Distance(100).percent_of_range(10, 110), 0);
+        assert_eq!(Distance(2_000).percent_of_range(10, 110), 100);
+    }
+
+    #[test]
+    fn distance_project() {
+        let dist = Distance(1_000);
+        assert_eq!(dist.project(0).mm(), 1_000);
+        assert_eq!(dist.project(30).mm(), 866);
+        assert_eq!(dist.project(45).mm(), 707);
+        assert_eq!(dist.project(60).mm(), 500);
+        assert_eq!(dist.project(90).mm(), 0);
+        assert_eq!(dist.project(200).mm(), 0);
+    }
+
+    #[test]
+    fn distance_packed12() {
+        assert_eq!(Distance(1_234).to_packed12(), 123);
+        assert_eq!(Distance::from_packed12(123).mm(), 1_230);
+        // The largest distance packed without saturating
+        assert_eq!(Distance(40_950).to_packed12(), 4_095);
+        assert_eq!(Distance::from_packed12(4_095).mm(), 40_950);
+        assert_eq!(Distance(40_959).to_packed12(), 4_095);
+        assert_eq!(Distance(50_000).to_packed12(), 0xFFF);
+        // The upper bits are ignored
+        assert_eq!(Distance::from_packed12(0xF07B).mm(), 1_230);
+        assert_eq!(Distance::from_packed12(0xFFFF).mm(), 40_950);
+    }
+
+    #[test]
+    fn conversions_saturate() {
+        let max = u32::max_value();
+        let dist = Distance(max);
+        assert_eq!(Distance::from_ticks(0, max, 1, max).mm(), max);
+        assert_eq!(dist.cm(), 429_496_729);
+        assert_eq!(dist.cm_i32(), 429_496_729);
+        assert_eq!(dist.tenths_inch(), 1_690_931_966);
+        assert_eq!(dist.in_unit(Unit::Mm), max);
+        assert_eq!(dist.in_unit(Unit::Cm), 429_496_729);
+        assert_eq!(dist.in_unit(Unit::Inch), 169_093_196);
+        assert_eq!(dist.in_unit_rounded(Unit::Cm, RoundingMode::HalfUp), 429_496_730);
+        assert_eq!(dist.in_unit_rounded(Unit::Cm, RoundingMode::HalfEven), 429_496_730);
+        assert_eq!(dist.scaled(1), max);
+        assert_eq!(dist.project(0).mm(), max);
+        assert_eq!(dist.project(60).mm(), 2_147_483_648);
+        assert_eq!(dist.to_duty(10, 110, 1_000), 1_000);
+        assert_eq!(dist.percent_of_range(10, 110), 100);
+        assert_eq!(dist.ratio_to(Distance(1)), u16::max_value());
+        assert_eq!(Distance(1).ratio_to(dist), 0);
+        assert_eq!(dist.meters_whole_and_mm(), (4_294_967, 295));
+        assert_eq!(dist.meters_q16(), max);
+        assert_eq!(dist.to_packed12(), 0xFFF);
+    }
+
+    #[test]
+    fn temperatures_below_freezing() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_temperature(-20);
+        assert_eq!(sensor.speed_factor(), 159_485);
+        assert_eq!(measure(&mut sensor, 5_800), 925);
+        sensor.set_temperature(-40);
+        assert_eq!(sensor.speed_factor(), 153_425);
+        assert_eq!(measure(&mut sensor, 5_800), 889);
+        assert_eq!(sensor.temperature(), Some(-40));
+    }
+
+    #[test]
+    fn speed_of_sound_sets_factor() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_temperature(30);
+        sensor.set_speed_of_sound_mps(343);
+        assert_eq!(sensor.speed_factor(), 171_500);
+        assert_eq!(sensor.temperature(), None);
+        assert_eq!(measure(&mut sensor, 5_800), 994);
+        // Sound travels more than four times as fast in water
+        sensor.set_speed_of_sound_mps(1_480);
+        assert_eq!(sensor.speed_factor(), 740_000);
+        assert_eq!(measure(&mut sensor, 5_800), 4_292);
+    }
+
+    #[test]
+    fn reading_filter_rejects_readings() {
+        /// Accept readings up to a meter
+        fn near(dist: Distance) -> bool {
+            dist.mm() <= 1_000
+        }
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_reading_filter(Some(near));
+        assert_eq!(measure(&mut sensor, 5_800), 995);
+        sensor.fire().unwrap();
+        sensor.capture_pair(0, 11_600).unwrap();
+        assert!(matches!(
+            sensor.distance(),
+            Err(Error::Other(SensorError::Rejected { ticks: 11_600 }))
+        ));
+        assert!(!sensor.last_reading_valid());
+        sensor.set_reading_filter(None);
+        assert_eq!(measure(&mut sensor, 11_600), 1_990);
+    }
+
+    #[test]
+    fn cycle_time_is_sum_of_parts() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        let cycle = consts::TRIGGER_WIDTH_US + consts::MEASUREMENT_CYCLE_US;
+        assert_eq!(sensor.cycle_time_us(), cycle);
+        sensor.set_settle_delay(10_000);
+        assert_eq!(sensor.cycle_time_us(), 10_000 + cycle);
+        sensor.set_settle_delay(u32::max_value());
+        assert_eq!(sensor.cycle_time_us(), u32::max_value());
+    }
+
+    #[test]
+    fn max_rate_follows_cycle_time() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        assert_eq!(sensor.max_rate_hz(), 16);
+        sensor.set_settle_delay(10_000);
+        assert_eq!(sensor.max_rate_hz(), 14);
+        assert_eq!(sensor.max_rate_hz(), 1_000_000 / sensor.cycle_time_us());
+        sensor.set_settle_delay(u32::max_value());
+        assert_eq!(sensor.max_rate_hz(), 0);
+    }
+
+    #[test]
+    fn resolution_follows_frequency() {
+        let clock = MockClock::new(1_000_000);
+        assert_eq!(mock::sensor(&clock).resolution_um(), 172);
+        let fast_clock = MockClock::new(72_000_000);
+        assert_eq!(mock::sensor(&fast_clock).resolution_um(), 3);
+    }
+
+    #[test]
+    fn config_follows_setters() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        assert_eq!(
+            sensor.config(),
+            Config {
+                frequency_hz: 1_000_000,
+                width_offset_ns: 0,
+                temperature: None,
+                trigger_width_us: consts::TRIGGER_WIDTH_US,
+                min_range_mm: consts::MIN_RANGE_MM,
+                max_range_mm: consts::MAX_RANGE_MM,
+                speed_factor: DEFAULT_SPEED_FACTOR,
+                debounce_us: DEFAULT_DEBOUNCE_US,
+                settle_us: 0,
+                pulse_limits_us: (consts::MIN_VALID_ECHO_US, consts::MAX_VALID_ECHO_US),
+                oversampling: 1,
+            }
+        );
+        sensor.set_capture_frequency(Hertz(2_000_000));
+        sensor.set_width_offset_ns(500);
+        sensor.set_temperature(-20);
+        sensor.set_scale_min_range(true);
+        sensor.set_max_distance(Some(Distance(2_000)));
+        sensor.set_debounce(50);
+        sensor.set_settle_delay(1_000);
+        sensor.set_pulse_limits(200, 20_000);
+        sensor.set_oversampling(4);
+        assert_eq!(
+            sensor.config(),
+            Config {
+                frequency_hz: 2_000_000,
+                width_offset_ns: 500,
+                temperature: Some(-20),
+                trigger_width_us: consts::TRIGGER_WIDTH_US,
+                min_range_mm: 18,
+                max_range_mm: 2_000,
+                speed_factor: 159_485,
+                debounce_us: 50,
+                settle_us: 1_000,
+                pulse_limits_us: (200, 20_000),
+                oversampling: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn poll_echo_glitch_before_pulse() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.fire().unwrap();
+        // A spike on the echo line lasting a single sample, its falling edge
+        // is ignored by the debouncing
+        sensor.poll_echo(true).unwrap();
+        clock.advance(10);
+        sensor.poll_echo(false).unwrap();
+        // The line stays low until the return pulse
+        for _ in 0..48 {
+            clock.advance(10);
+            sensor.poll_echo(false).unwrap();
+        }
+        assert_eq!(sensor.state_code(), 2);
+        clock.advance(10);
+        sensor.poll_echo(true).unwrap();
+        for _ in 0..579 {
+            clock.advance(10);
+            sensor.poll_echo(true).unwrap();
+        }
+        clock.advance(10);
+        sensor.poll_echo(false).unwrap();
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+    }
+
+    #[test]
+    fn no_object_detected() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.debug_preload(0, NO_OBJECT_US - 1);
+        assert!(matches!(
+            sensor.distance(),
+            Err(Error::Other(SensorError::OutOfSpec { .. }))
+        ));
+        sensor.debug_preload(0, NO_OBJECT_US);
+        assert!(matches!(
+            sensor.distance(),
+            Err(Error::Other(SensorError::NoObjectDetected { ticks: NO_OBJECT_US }))
+        ));
+        sensor.debug_preload(0, ECHO_STUCK_MS * 1_000);
+        assert!(matches!(
+            sensor.distance(),
+            Err(Error::Other(SensorError::NoObjectDetected { .. }))
+        ));
+        sensor.debug_preload(0, ECHO_STUCK_MS * 1_000 + 1);
+        assert!(matches!(
+            sensor.distance(),
+            Err(Error::Other(SensorError::ImplausiblePulse { .. }))
+        ));
+    }
+
+    #[test]
+    fn multi_second_pulse_is_not_no_object() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.fire().unwrap();
+        sensor.update().unwrap();
+        // The falling edge arrives seconds late, without `distance` being
+        // polled in between
+        clock.advance(5_000_000);
+        sensor.update().unwrap();
+        assert!(matches!(
+            sensor.distance(),
+            Err(Error::Other(SensorError::ImplausiblePulse { ticks: 5_000_000 }))
+        ));
+    }
+
+    #[test]
+    fn implausible_pulses() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        // Garbage timestamps are not mistaken for a clear path
+        sensor.fire().unwrap();
+        sensor.capture_pair(0, 0xF000_0000).unwrap();
+        assert!(matches!(
+            sensor.distance(),
+            Err(Error::Other(SensorError::ImplausiblePulse { ticks: 0xF000_0000 }))
+        ));
+        sensor.set_max_distance(Some(Distance(1_000)));
+        sensor.debug_preload(0, 5_800);
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+        sensor.debug_preload(0, 11_600);
+        assert!(matches!(
+            sensor.distance(),
+            Err(Error::Other(SensorError::ImplausiblePulse { ticks: 11_600 }))
+        ));
+        assert!(!sensor.last_reading_valid());
+    }
+
+    /// Complete the second measurement of a reading oversampled twice with
+    /// `sensor`, which must have just started the reading over
+    fn finish_burst(sensor: &mut mock::MockSensor) -> u32 {
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        sensor.capture_pair(0, 5_800).unwrap();
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        sensor.capture_pair(0, 5_800).unwrap();
+        sensor.distance().unwrap().mm()
+    }
+
+    #[test]
+    fn timeout_discards_burst() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_oversampling(2);
+        sensor.fire().unwrap();
+        sensor.capture_pair(0, 11_600).unwrap();
+        // The first measurement of the reading triggers the second
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        sensor.timedout();
+        // The reading starts over without the first measurement
+        assert_eq!(finish_burst(&mut sensor), 995);
+    }
+
+    #[test]
+    fn echo_stuck_discards_burst() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_oversampling(2);
+        sensor.fire().unwrap();
+        sensor.capture_pair(0, 11_600).unwrap();
+        assert!(matches!(sensor.distance(), Err(Error::WouldBlock)));
+        sensor.update().unwrap();
+        clock.advance(ECHO_STUCK_MS * 1_000 + 1);
+        assert!(matches!(
+            sensor.distance(),
+            Err(Error::Other(SensorError::EchoStuck { .. }))
+        ));
+        assert_eq!(finish_burst(&mut sensor), 995);
+    }
+
+    #[test]
+    fn reset_on_gap_through_driver() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = builder::HcSr04Builder::new(
+            mock::MockPin { high: false },
+            mock::MockDelay { clock: &clock },
+            &clock,
+        ).filter(filter::ResetOnGap::new(filter::MovingAverage::<4>::new(), 100))
+            .build()
+            .ok()
+            .unwrap();
+        sensor.debug_preload(0, 5_800);
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+        clock.advance(50_000);
+        sensor.debug_preload(0, 11_600);
+        assert_eq!(sensor.distance().unwrap().mm(), 1_492);
+        // Readings resume after a gap, the average starts over
+        clock.advance(200_000);
+        sensor.debug_preload(0, 5_800);
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+    }
+
+    #[test]
+    fn capture_with_period_wraps() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.fire().unwrap();
+        sensor.capture_with_period(65_000, u16::max_value()).unwrap();
+        sensor.capture_with_period(5_264, u16::max_value()).unwrap();
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+        assert_eq!(sensor.counter_period(), Some(65_536));
+
+        sensor.fire().unwrap();
+        sensor.capture_with_period(9_000, 9_999).unwrap();
+        sensor.capture_with_period(4_800, 9_999).unwrap();
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+        assert_eq!(sensor.counter_period(), Some(10_000));
+    }
+
+    #[test]
+    fn capture_with_period_validates_timestamps() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        assert!(matches!(
+            sensor.capture_with_period(100, 9_999),
+            Err(SensorError::WrongMode)
+        ));
+        sensor.fire().unwrap();
+        assert!(matches!(
+            sensor.capture_with_period(10_000, 9_999),
+            Err(SensorError::InvalidTimestamp { timestamp: 10_000 })
+        ));
+        assert_eq!(sensor.state_code(), 1);
+        // The period shrinks below the rising edge during the pulse
+        sensor.capture_with_period(9_000, 9_999).unwrap();
+        assert!(matches!(
+            sensor.capture_with_period(100, 999),
+            Err(SensorError::ImplausiblePulse { .. })
+        ));
+        assert_eq!(sensor.state_code(), 3);
+    }
+
+    #[test]
+    fn capture_with_period_debounces() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        let top = u16::max_value();
+        // Falling and rising edge of a glitch during the pulse
+        sensor.fire().unwrap();
+        sensor.capture_with_period(100, top).unwrap();
+        sensor.capture_with_period(150, top).unwrap();
+        sensor.capture_with_period(210, top).unwrap();
+        assert_eq!(sensor.state_code(), 3);
+        sensor.capture_with_period(5_900, top).unwrap();
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+
+        // A spike before the return pulse leaves the line low
+        sensor.fire().unwrap();
+        sensor.capture_with_period(100, top).unwrap();
+        sensor.capture_with_period(120, top).unwrap();
+        sensor.capture_with_period(600, top).unwrap();
+        sensor.capture_with_period(12_200, top).unwrap();
+        assert_eq!(sensor.distance().unwrap().mm(), 1_990);
+    }
+
+    #[test]
+    fn zero_capture_frequency_is_ignored() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_capture_frequency(Hertz(2_000_000));
+        sensor.set_capture_frequency(Hertz(0));
+        assert_eq!(sensor.config().frequency_hz, 2_000_000);
+    }
+
+    #[test]
+    fn distance_from_elapsed_ticks() {
+        // Elapsed ticks are passed as the end of a pulse starting at 0
+        let dist = Distance::from_ticks(0, 41_760, 7_200_000, DEFAULT_SPEED_FACTOR);
+        assert_eq!(dist.mm(), 995);
+    }
+
+    #[test]
+    #[cfg(feature = "heapless")]
+    fn reading_log_in_driver() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = builder::HcSr04Builder::new(
+            mock::MockPin { high: false },
+            mock::MockDelay { clock: &clock },
+            &clock,
+        ).filter(stats::ReadingLog::<4>::new())
+            .build()
+            .ok()
+            .unwrap();
+        sensor.debug_preload(0, 5_800);
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+        sensor.debug_preload(0, 11_600);
+        assert_eq!(sensor.distance().unwrap().mm(), 1_990);
+        assert_eq!(sensor.filter().len(), 2);
+        assert_eq!(sensor.filter().latest().map(|d| d.mm()), Some(1_990));
+        sensor.filter_mut().reset();
+        assert!(sensor.filter().is_empty());
+    }
+
+    #[test]
+    fn nearest_valid_reading() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensors = [mock::sensor(&clock), mock::sensor(&clock), mock::sensor(&clock)];
+        assert!(HcSr04::nearest(&sensors).is_none());
+        sensors[0].fire().unwrap();
+        sensors[0].capture_pair(0, 11_600).unwrap();
+        sensors[2].fire().unwrap();
+        sensors[2].capture_pair(0, 5_800).unwrap();
+        // The sensor without a reading is skipped
+        let (i, dist) = HcSr04::nearest(&sensors).unwrap();
+        assert_eq!((i, dist.mm()), (2, 995));
+        // Readings out of range are skipped
+        sensors[2].set_max_distance(Some(Distance(500)));
+        let (i, dist) = HcSr04::nearest(&sensors).unwrap();
+        assert_eq!((i, dist.mm()), (0, 1_990));
+    }
+
+    #[test]
+    fn zero_frequency_clock_does_not_panic() {
+        let clock = MockClock::new(0);
+        let mut sensor = mock::sensor(&clock);
+        sensor.fire().unwrap();
+        assert!(sensor.capture_pair(0, 5_800).is_err());
+        let mut sensor = mock::sensor(&clock);
+        sensor.fire().unwrap();
+        sensor.update().unwrap();
+        clock.advance(5_800);
+        sensor.update().unwrap();
+        assert!(sensor.distance().is_err());
+        assert!(sensor.last_latency_us().is_some());
+        assert!(sensor.trigger_jitter_ns().is_some());
+        sensor.active_time_us();
+        assert_eq!(sensor.resolution_um(), sensor.speed_factor() * 1_000);
+    }
+
+    #[test]
+    fn cable_delay_sets_width_offset() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_cable_delay_ns(100_000);
+        assert_eq!(sensor.config().width_offset_ns, 100_000);
+        sensor.debug_preload(0, 5_900);
+        assert_eq!(sensor.distance().unwrap().mm(), 995);
+    }
+
+    #[test]
+    fn scaled_min_range_does_not_clamp_readings() {
+        let clock = MockClock::new(1_000_000);
+        let mut sensor = mock::sensor(&clock);
+        sensor.set_temperature(-40);
+        sensor.set_scale_min_range(true);
+        sensor.set_debounce(10);
+        sensor.set_pulse_limits(50, consts::MAX_VALID_ECHO_US);
+        // The reading below the minimum range is returned but flagged
+        let mm = measure(&mut sensor, 100);
+        assert!(0 < mm && mm < sensor.min_range().mm());
+        assert!(!sensor.last_reading_valid());
     }
 }
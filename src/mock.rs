@@ -0,0 +1,95 @@
+//! Mock peripherals for testing the driver on the host.
+
+use core::cell::Cell;
+use hal::blocking::delay::DelayUs;
+use hal::digital::{InputPin, OutputPin};
+use hal::timer::CountDown;
+use nb;
+use void::Void;
+use {Clock, HcSr04, MockClock, NoTimeout};
+
+/// Trigger pin remembering its level
+pub struct MockPin {
+    /// Current level of the pin
+    pub high: bool,
+}
+
+impl OutputPin for MockPin {
+    fn is_high(&self) -> bool {
+        self.high
+    }
+
+    fn is_low(&self) -> bool {
+        !self.high
+    }
+
+    fn set_low(&mut self) {
+        self.high = false;
+    }
+
+    fn set_high(&mut self) {
+        self.high = true;
+    }
+}
+
+/// Echo pin at a fixed level
+pub struct MockEcho {
+    /// Level of the pin
+    pub high: bool,
+}
+
+impl InputPin for MockEcho {
+    fn is_high(&self) -> bool {
+        self.high
+    }
+
+    fn is_low(&self) -> bool {
+        !self.high
+    }
+}
+
+/// Timeout timer expiring when told to
+pub struct MockTimeout<'a> {
+    /// Set to expire the timer, cleared when it is started
+    pub expired: &'a Cell<bool>,
+}
+
+impl<'a> CountDown for MockTimeout<'a> {
+    type Time = ();
+
+    fn start<T>(&mut self, _count: T)
+    where
+        T: Into<()>,
+    {
+        self.expired.set(false);
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        if self.expired.get() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+/// Delay advancing a `MockClock` instead of waiting
+pub struct MockDelay<'a> {
+    /// Clock to advance
+    pub clock: &'a MockClock,
+}
+
+impl<'a> DelayUs<u32> for MockDelay<'a> {
+    fn delay_us(&mut self, us: u32) {
+        let ticks = us as u64 * self.clock.frequency() as u64 / 1_000_000;
+        self.clock.advance(ticks as u32);
+    }
+}
+
+/// Driver timed by `clock`
+pub type MockSensor<'a> = HcSr04<MockPin, MockDelay<'a>, NoTimeout, &'a MockClock>;
+
+/// Create a driver timed by `clock`
+pub fn sensor(clock: &MockClock) -> MockSensor {
+    HcSr04::new(MockPin { high: false }, MockDelay { clock: clock }, clock)
+}
@@ -0,0 +1,197 @@
+//! Scheduling of measurements across several sensors.
+
+use nb;
+use OneShot;
+
+/// Sensor of a `PingPong` pair.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Side {
+    /// The first sensor given to `PingPong::new`
+    A,
+    /// The second sensor given to `PingPong::new`
+    B,
+}
+
+/// Alternate measurements between two sensors.
+///
+/// Two sensors measuring at the same time can pick up each other's pulses,
+/// e.g. a left and right facing pair. This scheduler only reads one sensor
+/// at a time, waiting for the result of one before starting the other:
+///
+/// ```ignore
+/// let mut pair = PingPong::new(left, right);
+/// match pair.read() {
+///     Ok((Side::A, dist)) => ...,
+///     Ok((Side::B, dist)) => ...,
+///     ...
+/// }
+/// ```
+///
+/// Each result, distance or error, is labeled with the `Side` it came from
+/// and ends the turn of that sensor. The sensors are owned by the scheduler,
+/// use `a_mut` and `b_mut` to e.g. call `HcSr04::update` from interrupts.
+///
+/// # Rate
+/// The sensors share one measurement cycle, so each is read at half the
+/// rate it could be read at alone, and the combined rate is that of a
+/// single sensor. The next sensor is triggered as soon as the previous one
+/// returns its result, to avoid the previous pulse being picked up leave a
+/// gap with `HcSr04::set_settle_delay`. With a full measurement cycle
+/// (`consts::MEASUREMENT_CYCLE_US`) per reading the combined rate is about
+/// 16 Hz, i.e. 8 Hz for each sensor.
+///
+/// # Note
+/// The sensors must report `WouldBlock` while measuring, do not combine
+/// this with `HcSr04::set_return_last_while_busy`.
+pub struct PingPong<A, B> {
+    /// First sensor
+    a: A,
+    /// Second sensor
+    b: B,
+    /// Sensor currently measuring
+    turn: Side,
+}
+
+impl<A, B> PingPong<A, B>
+where
+    A: OneShot,
+    B: OneShot<Word = A::Word, Error = A::Error>,
+{
+    /// Create a new scheduler, starting with `a`.
+    pub fn new(a: A, b: B) -> Self {
+        PingPong {
+            a: a,
+            b: b,
+            turn: Side::A,
+        }
+    }
+
+    /// Read the sensor whose turn it is, returning `WouldBlock` until it is
+    /// done.
+    ///
+    /// Once the sensor returns a result, or an error, the turn passes to
+    /// the other sensor which is triggered on the next call.
+    pub fn read(&mut self) -> nb::Result<(Side, A::Word), (Side, A::Error)> {
+        let side = self.turn;
+        let result = match side {
+            Side::A => self.a.read(),
+            Side::B => self.b.read(),
+        };
+        let result = match result {
+            Err(nb::Error::WouldBlock) => return Err(nb::Error::WouldBlock),
+            Ok(value) => Ok((side, value)),
+            Err(nb::Error::Other(err)) => Err(nb::Error::Other((side, err))),
+        };
+        self.turn = match side {
+            Side::A => Side::B,
+            Side::B => Side::A,
+        };
+        result
+    }
+
+    /// Get the sensor whose turn it is.
+    pub fn turn(&self) -> Side {
+        self.turn
+    }
+
+    /// Get mutable access to the first sensor.
+    pub fn a_mut(&mut self) -> &mut A {
+        &mut self.a
+    }
+
+    /// Get mutable access to the second sensor.
+    pub fn b_mut(&mut self) -> &mut B {
+        &mut self.b
+    }
+
+    /// Release the sensors.
+    pub fn free(self) -> (A, B) {
+        (self.a, self.b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reply of a `Dummy` sensor to one read
+    #[derive(Copy, Clone)]
+    enum Reply {
+        Value(u32),
+        Fail(u8),
+        Busy,
+    }
+
+    /// Sensor replying with a fixed sequence, repeating the last reply
+    struct Dummy {
+        replies: &'static [Reply],
+        next: usize,
+    }
+
+    impl Dummy {
+        fn new(replies: &'static [Reply]) -> Self {
+            Dummy {
+                replies: replies,
+                next: 0,
+            }
+        }
+    }
+
+    impl OneShot for Dummy {
+        type Word = u32;
+        type Error = u8;
+
+        fn read(&mut self) -> nb::Result<u32, u8> {
+            let reply = self.replies[self.next.min(self.replies.len() - 1)];
+            self.next += 1;
+            match reply {
+                Reply::Value(value) => Ok(value),
+                Reply::Fail(err) => Err(nb::Error::Other(err)),
+                Reply::Busy => Err(nb::Error::WouldBlock),
+            }
+        }
+    }
+
+    #[test]
+    fn alternates() {
+        let mut pair = PingPong::new(
+            Dummy::new(&[Reply::Value(1)]),
+            Dummy::new(&[Reply::Value(2)]),
+        );
+        assert_eq!(pair.turn(), Side::A);
+        assert!(matches!(pair.read(), Ok((Side::A, 1))));
+        assert_eq!(pair.turn(), Side::B);
+        assert!(matches!(pair.read(), Ok((Side::B, 2))));
+        assert!(matches!(pair.read(), Ok((Side::A, 1))));
+        assert!(matches!(pair.read(), Ok((Side::B, 2))));
+    }
+
+    #[test]
+    fn error_ends_turn() {
+        let mut pair = PingPong::new(
+            Dummy::new(&[Reply::Fail(7), Reply::Value(1)]),
+            Dummy::new(&[Reply::Fail(8), Reply::Value(2)]),
+        );
+        assert!(matches!(pair.read(), Err(nb::Error::Other((Side::A, 7)))));
+        assert_eq!(pair.turn(), Side::B);
+        assert!(matches!(pair.read(), Err(nb::Error::Other((Side::B, 8)))));
+        assert_eq!(pair.turn(), Side::A);
+        assert!(matches!(pair.read(), Ok((Side::A, 1))));
+    }
+
+    #[test]
+    fn would_block_keeps_turn() {
+        let mut pair = PingPong::new(
+            Dummy::new(&[Reply::Busy, Reply::Busy, Reply::Value(1)]),
+            Dummy::new(&[Reply::Value(2)]),
+        );
+        assert!(matches!(pair.read(), Err(nb::Error::WouldBlock)));
+        assert_eq!(pair.turn(), Side::A);
+        assert!(matches!(pair.read(), Err(nb::Error::WouldBlock)));
+        assert_eq!(pair.turn(), Side::A);
+        assert!(matches!(pair.read(), Ok((Side::A, 1))));
+        assert_eq!(pair.turn(), Side::B);
+        let (a, b) = pair.free();
+        assert_eq!((a.next, b.next), (3, 0));
+    }
+}
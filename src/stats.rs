@@ -0,0 +1,451 @@
+//! Tools for analysing a series of readings from the sensor.
+
+#[cfg(feature = "heapless")]
+use heapless::HistoryBuffer;
+use filter::{Filter, Window};
+use Distance;
+
+/// Histogram of distances for noise analysis.
+///
+/// Readings between `min` and `max` are counted in `BINS` equally sized bins,
+/// readings outside of this range are only counted as outside.
+pub struct DistanceHistogram<const BINS: usize> {
+    /// Lower bound of the first bin in millimeters
+    min: u32,
+    /// Upper bound of the last bin in millimeters
+    max: u32,
+    /// Number of readings in each bin
+    bins: [u32; BINS],
+    /// Number of readings outside `min..max`
+    outside: u32,
+}
+
+impl<const BINS: usize> DistanceHistogram<BINS> {
+    /// Create a new empty histogram covering `min..max`.
+    ///
+    /// # Panics
+    /// Panics if `min` is not smaller than `max` or `BINS` is zero.
+    pub fn new(min: Distance, max: Distance) -> Self {
+        assert!(min.mm() < max.mm(), "Histogram range must not be empty");
+        assert!(BINS > 0, "Histogram must have at least one bin");
+        DistanceHistogram {
+            min: min.mm(),
+            max: max.mm(),
+            bins: [0; BINS],
+            outside: 0,
+        }
+    }
+
+    /// Add a reading to the histogram.
+    pub fn add(&mut self, distance: Distance) {
+        let mm = distance.mm();
+        if mm < self.min || mm >= self.max {
+            self.outside = self.outside.saturating_add(1);
+            return;
+        }
+        let bin = (mm - self.min) as u64 * BINS as u64 / (self.max - self.min) as u64;
+        self.bins[bin as usize] = self.bins[bin as usize].saturating_add(1);
+    }
+
+    /// Get the number of readings in each bin.
+    pub fn bins(&self) -> &[u32; BINS] {
+        &self.bins
+    }
+
+    /// Get the number of readings which fell outside the histogram range.
+    pub fn outside(&self) -> u32 {
+        self.outside
+    }
+
+    /// Get the range of distances, `(lower, upper)`, covered by `bin`.
+    ///
+    /// The lower bound is inclusive while the upper bound is exclusive.
+    pub fn bin_range(&self, bin: usize) -> (Distance, Distance) {
+        let width = (self.max - self.min) as u64;
+        let lower = self.min as u64 + width * bin as u64 / BINS as u64;
+        let upper = self.min as u64 + width * (bin as u64 + 1) / BINS as u64;
+        (Distance(lower as u32), Distance(upper as u32))
+    }
+
+    /// Get the index of the bin with the most readings.
+    ///
+    /// Returns `None` if no readings have been added within range. If several
+    /// bins share the highest count the first one is returned.
+    pub fn most_frequent(&self) -> Option<usize> {
+        let mut best = None;
+        let mut best_count = 0;
+        for (i, &count) in self.bins.iter().enumerate() {
+            if count > best_count {
+                best = Some(i);
+                best_count = count;
+            }
+        }
+        best
+    }
+
+    /// Remove all readings from the histogram.
+    pub fn clear(&mut self) {
+        self.bins = [0; BINS];
+        self.outside = 0;
+    }
+}
+
+/// Track the smallest and largest distance observed.
+///
+/// Useful for e.g. detecting the nearest approach of a passing object.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PeakHold {
+    /// Smallest and largest reading seen since reset
+    peaks: Option<(Distance, Distance)>,
+}
+
+impl PeakHold {
+    /// Create a new peak hold without any readings.
+    pub fn new() -> Self {
+        PeakHold { peaks: None }
+    }
+
+    /// Add a reading, updating the extremes.
+    pub fn add(&mut self, distance: Distance) {
+        self.peaks = Some(match self.peaks {
+            Some((min, max)) => (
+                if distance.mm() < min.mm() { distance } else { min },
+                if distance.mm() > max.mm() { distance } else { max },
+            ),
+            None => (distance, distance),
+        });
+    }
+
+    /// Get the smallest distance seen since reset.
+    pub fn min(&self) -> Option<Distance> {
+        self.peaks.map(|(min, _)| min)
+    }
+
+    /// Get the largest distance seen since reset.
+    pub fn max(&self) -> Option<Distance> {
+        self.peaks.map(|(_, max)| max)
+    }
+
+    /// Forget all readings.
+    pub fn reset(&mut self) {
+        self.peaks = None;
+    }
+}
+
+/// Log of the most recent readings, for dumping diagnostics on demand.
+///
+/// Keeps the last `N` readings, older readings are overwritten. Only
+/// available with the `heapless` feature.
+///
+/// The log is also a `Filter` which passes readings through unchanged, so
+/// that it can be attached to a driver with `HcSr04Builder::filter` to log
+/// every reading returned from `distance`, see `HcSr04::filter` to read the
+/// log. Chain it after another filter to log the filtered readings:
+///
+/// ```ignore
+/// let filter = Median::<5>::new().chain(ReadingLog::<32>::new());
+/// ```
+#[cfg(feature = "heapless")]
+pub struct ReadingLog<const N: usize> {
+    /// Most recent readings
+    readings: HistoryBuffer<Distance, N>,
+}
+
+#[cfg(feature = "heapless")]
+impl<const N: usize> ReadingLog<N> {
+    /// Create a new empty log.
+    pub fn new() -> Self {
+        ReadingLog {
+            readings: HistoryBuffer::new(),
+        }
+    }
+
+    /// Add a reading to the log.
+    pub fn add(&mut self, distance: Distance) {
+        self.readings.write(distance);
+    }
+
+    /// Get the most recent reading.
+    pub fn latest(&self) -> Option<Distance> {
+        self.readings.recent().cloned()
+    }
+
+    /// Iterate over the logged readings from oldest to newest.
+    pub fn readings(&self) -> impl Iterator<Item = &Distance> {
+        self.readings.oldest_ordered()
+    }
+
+    /// Get the number of logged readings.
+    pub fn len(&self) -> usize {
+        self.readings.len()
+    }
+
+    /// Check if the log is empty.
+    pub fn is_empty(&self) -> bool {
+        self.readings.len() == 0
+    }
+
+    /// Remove all readings from the log.
+    pub fn clear(&mut self) {
+        self.readings.clear();
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<const N: usize> Default for ReadingLog<N> {
+    fn default() -> Self {
+        ReadingLog::new()
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<const N: usize> Filter for ReadingLog<N> {
+    fn update(&mut self, distance: Distance) -> Distance {
+        self.add(distance);
+        distance
+    }
+
+    fn reset(&mut self) {
+        self.clear();
+    }
+}
+
+/// Detect whether readings have settled.
+///
+/// Readings are considered stable when the last `N` readings all lie within
+/// `threshold` millimeters of each other, i.e. the difference between the
+/// largest and smallest of them is at most `threshold`. Until `N` readings
+/// have been added the readings are never considered stable.
+pub struct StabilityDetector<const N: usize> {
+    /// Last `N` readings
+    window: Window<N>,
+    /// Largest spread in millimeters of stable readings
+    threshold: u32,
+}
+
+impl<const N: usize> StabilityDetector<N> {
+    /// Create a new detector with the given threshold in millimeters.
+    pub fn new(threshold: u32) -> Self {
+        StabilityDetector {
+            window: Window::new(),
+            threshold: threshold,
+        }
+    }
+
+    /// Add a reading and return whether the readings are now stable.
+    pub fn add(&mut self, distance: Distance) -> bool {
+        self.window.push(distance.mm());
+        self.is_stable()
+    }
+
+    /// Check whether the last `N` readings are stable.
+    pub fn is_stable(&self) -> bool {
+        let values = self.window.values();
+        if values.len() < N {
+            return false;
+        }
+        let min = values.iter().min().cloned().unwrap_or(0);
+        let max = values.iter().max().cloned().unwrap_or(0);
+        max - min <= self.threshold
+    }
+
+    /// Forget all readings.
+    pub fn reset(&mut self) {
+        self.window.clear();
+    }
+}
+
+/// Detect sudden changes in a series of readings, e.g. an obstacle
+/// appearing.
+///
+/// A target moving at a steady speed changes the distance by the same amount
+/// between readings. This tracks the second difference of the last three
+/// readings, `d[n] - 2 * d[n - 1] + d[n - 2]` in millimeters, i.e. the change
+/// in speed per reading squared, and flags a sudden change when its
+/// magnitude exceeds the threshold. Steady motion and slow drift are
+/// therefore ignored while something jumping into view is caught on the
+/// reading it appears. The threshold should be well above the noise of the
+/// sensor, since noise on a single reading shows up about twice as large in
+/// the second difference.
+pub struct SuddenChangeDetector {
+    /// Largest second difference in millimeters considered normal
+    threshold: u32,
+    /// Previous two readings in millimeters, oldest first
+    history: (Option<u32>, Option<u32>),
+    /// Second difference of the last three readings in millimeters
+    acceleration: Option<i64>,
+}
+
+impl SuddenChangeDetector {
+    /// Create a new detector with the given threshold in millimeters.
+    pub fn new(threshold: u32) -> Self {
+        SuddenChangeDetector {
+            threshold: threshold,
+            history: (None, None),
+            acceleration: None,
+        }
+    }
+
+    /// Add a reading and return whether it was a sudden change.
+    ///
+    /// The first two readings are never flagged since there is nothing to
+    /// compare them against yet.
+    pub fn add(&mut self, distance: Distance) -> bool {
+        let mm = distance.mm();
+        self.acceleration = match self.history {
+            (Some(older), Some(newer)) => Some(mm as i64 - 2 * newer as i64 + older as i64),
+            _ => None,
+        };
+        self.history = (self.history.1, Some(mm));
+        self.acceleration.map_or(false, |acc| acc.abs() > self.threshold as i64)
+    }
+
+    /// Get the second difference in millimeters of the last three readings.
+    ///
+    /// Returns `None` until three readings have been added.
+    pub fn acceleration(&self) -> Option<i64> {
+        self.acceleration
+    }
+
+    /// Forget all readings.
+    pub fn reset(&mut self) {
+        self.history = (None, None);
+        self.acceleration = None;
+    }
+}
+
+/// Estimate a crude signal-to-noise ratio of a series of readings.
+///
+/// The ratio is computed as the mean of the readings divided by their
+/// standard deviation, both in millimeters, e.g. readings of 1 m with a
+/// standard deviation of 5 mm give a ratio of 200. A low ratio indicates a
+/// poor target or interference. If all readings are equal `u16::MAX` is
+/// returned, and an empty series gives `0`.
+///
+/// The readings would typically be the result of pinging the same target a
+/// number of times in a row.
+pub fn snr(readings: &[Distance]) -> u16 {
+    if readings.is_empty() {
+        return 0;
+    }
+    let n = readings.len() as u64;
+    let mean = readings.iter().map(|d| d.mm() as u64).sum::<u64>() / n;
+    let variance = readings
+        .iter()
+        .map(|d| {
+            let diff = d.mm() as i64 - mean as i64;
+            (diff * diff) as u64
+        })
+        .sum::<u64>() / n;
+    snr_from_moments(mean, variance)
+}
+
+/// Signal-to-noise ratio as for `snr` from the mean and variance of the
+/// readings in millimeters
+pub(crate) fn snr_from_moments(mean: u64, variance: u64) -> u16 {
+    let std_dev = isqrt(variance);
+    if std_dev == 0 {
+        return u16::max_value();
+    }
+    (mean / std_dev).min(u16::max_value() as u64) as u16
+}
+
+/// Integer square root, rounded down
+fn isqrt(value: u64) -> u64 {
+    if value < 2 {
+        return value;
+    }
+    // Newton's method starting from a value known to be too large, the
+    // first step halves it rounding up without overflowing
+    let mut x = value;
+    let mut y = x / 2 + (x & 1);
+    while y < x {
+        x = y;
+        y = (x + value / x) / 2;
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_bins() {
+        let mut histogram = DistanceHistogram::<4>::new(Distance(1_000), Distance(2_000));
+        assert_eq!(histogram.most_frequent(), None);
+        for &mm in &[1_000, 1_249, 1_250, 1_999, 2_000, 999] {
+            histogram.add(Distance(mm));
+        }
+        assert_eq!(histogram.bins(), &[2, 1, 0, 1]);
+        assert_eq!(histogram.outside(), 2);
+        assert_eq!(histogram.most_frequent(), Some(0));
+        let (lower, upper) = histogram.bin_range(1);
+        assert_eq!((lower.mm(), upper.mm()), (1_250, 1_500));
+        histogram.clear();
+        assert_eq!(histogram.bins(), &[0; 4]);
+        assert_eq!(histogram.outside(), 0);
+    }
+
+    fn histogram_empty_range() {
+        DistanceHistogram::<4>::new(Distance(1_000), Distance(1_000));
+    }
+
+    #[test]
+    fn peak_hold() {
+        let mut peaks = PeakHold::new();
+        assert!(peaks.min().is_none());
+        for &mm in &[1_000, 800, 1_200, 900] {
+            peaks.add(Distance(mm));
+        }
+        assert_eq!(peaks.min().map(|d| d.mm()), Some(800));
+        assert_eq!(peaks.max().map(|d| d.mm()), Some(1_200));
+        peaks.reset();
+        assert!(peaks.max().is_none());
+    }
+
+    #[test]
+    fn stability() {
+        let mut detector = StabilityDetector::<3>::new(10);
+        assert!(!detector.add(Distance(1_000)));
+        assert!(!detector.add(Distance(1_005)));
+        assert!(detector.add(Distance(1_010)));
+        assert!(!detector.add(Distance(1_021)));
+        assert!(!detector.add(Distance(1_015)));
+        assert!(detector.add(Distance(1_020)));
+        detector.reset();
+        assert!(!detector.is_stable());
+    }
+
+    #[test]
+    fn sudden_change() {
+        let mut detector = SuddenChangeDetector::new(50);
+        assert!(!detector.add(Distance(1_000)));
+        assert!(!detector.add(Distance(1_100)));
+        assert_eq!(detector.acceleration(), None);
+        // Steady motion is not a sudden change
+        assert!(!detector.add(Distance(1_200)));
+        assert_eq!(detector.acceleration(), Some(0));
+        assert!(detector.add(Distance(1_200)));
+        assert_eq!(detector.acceleration(), Some(-100));
+        detector.reset();
+        assert_eq!(detector.acceleration(), None);
+        assert!(!detector.add(Distance(1_000)));
+    }
+
+    #[test]
+    fn signal_to_noise() {
+        assert_eq!(snr(&[]), 0);
+        assert_eq!(snr(&[Distance(1_000), Distance(1_000)]), u16::max_value());
+        assert_eq!(snr(&[Distance(990), Distance(1_010)]), 100);
+        assert_eq!(snr_from_moments(u64::max_value(), 1), u16::max_value());
+    }
+
+    #[test]
+    fn integer_square_root() {
+        for &(value, root) in &[(0, 0), (1, 1), (2, 1), (3, 1), (4, 2), (99, 9), (100, 10)] {
+            assert_eq!(isqrt(value), root);
+        }
+        assert_eq!(isqrt(u64::max_value()), u32::max_value() as u64);
+    }
+}
@@ -0,0 +1,143 @@
+//! Classification of distances into proximity zones.
+
+use Distance;
+
+/// Proximity zone of a reading, ordered from closest to farthest.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Zone {
+    /// Object is touching or almost touching the sensor
+    Touch,
+    /// Object is close to the sensor
+    Near,
+    /// Object is at a medium distance from the sensor
+    Mid,
+    /// Object is far from the sensor, or nothing was detected
+    Far,
+}
+
+/// All zones indexed by their position
+const ZONES: [Zone; 4] = [Zone::Touch, Zone::Near, Zone::Mid, Zone::Far];
+
+/// Classify distances into `Zone`s with hysteresis.
+///
+/// Once a reading has been classified, later readings have to move more than
+/// the hysteresis beyond the boundaries of the current zone before the zone
+/// changes. This avoids flickering between two zones when an object sits
+/// right at a boundary.
+pub struct ZoneClassifier {
+    /// Upper bound in millimeters of `Touch`, `Near` and `Mid`
+    bounds: [u32; 3],
+    /// Distance in millimeters a reading must move past a boundary
+    hysteresis: u32,
+    /// Index of the zone of the last classified reading
+    current: Option<usize>,
+}
+
+impl ZoneClassifier {
+    /// Create a new classifier.
+    ///
+    /// # Arguments
+    /// - `touch`, `near` and `mid` are the upper bounds (exclusive) of the
+    /// respective zones, anything beyond `mid` is `Zone::Far`.
+    /// - `hysteresis` is how far a reading has to move past the boundaries
+    /// of the current zone before the zone changes.
+    ///
+    /// # Panics
+    /// Panics if the bounds are not strictly increasing.
+    pub fn new(touch: Distance, near: Distance, mid: Distance, hysteresis: Distance) -> Self {
+        assert!(
+            touch.mm() < near.mm() && near.mm() < mid.mm(),
+            "Zone boundaries must be increasing"
+        );
+        ZoneClassifier {
+            bounds: [touch.mm(), near.mm(), mid.mm()],
+            hysteresis: hysteresis.mm(),
+            current: None,
+        }
+    }
+
+    /// Classify a reading, updating the current zone.
+    pub fn classify(&mut self, distance: Distance) -> Zone {
+        let mm = distance.mm();
+        let zone = match self.current {
+            // Stay in the current zone as long as the reading is within the
+            // zone widened by the hysteresis on both sides
+            Some(cur) if self.within(cur, mm) => cur,
+            _ => self.bounds.iter().position(|&b| mm < b).unwrap_or(3),
+        };
+        self.current = Some(zone);
+        ZONES[zone]
+    }
+
+    /// Get the zone of the last classified reading.
+    pub fn zone(&self) -> Option<Zone> {
+        self.current.map(|z| ZONES[z])
+    }
+
+    /// Forget the current zone, the next reading is classified without
+    /// hysteresis.
+    pub fn reset(&mut self) {
+        self.current = None;
+    }
+
+    /// Check if `mm` is within zone `zone` widened by the hysteresis
+    fn within(&self, zone: usize, mm: u32) -> bool {
+        let lower = if zone == 0 { 0 } else { self.bounds[zone - 1] };
+        let above_lower = mm.saturating_add(self.hysteresis) >= lower;
+        let below_upper = match self.bounds.get(zone) {
+            Some(&upper) => mm < upper.saturating_add(self.hysteresis),
+            None => true,
+        };
+        above_lower && below_upper
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Classifier with zones up to 10 cm, 50 cm and 1.5 m
+    fn classifier() -> ZoneClassifier {
+        ZoneClassifier::new(Distance(100), Distance(500), Distance(1_500), Distance(20))
+    }
+
+    #[test]
+    fn classifies_without_history() {
+        let mut zones = classifier();
+        assert_eq!(zones.zone(), None);
+        for &(mm, zone) in &[
+            (0, Zone::Touch),
+            (99, Zone::Touch),
+            (100, Zone::Near),
+            (499, Zone::Near),
+            (500, Zone::Mid),
+            (1_500, Zone::Far),
+            (u32::max_value(), Zone::Far),
+        ] {
+            zones.reset();
+            assert_eq!(zones.classify(Distance(mm)), zone);
+        }
+        assert_eq!(zones.zone(), Some(Zone::Far));
+    }
+
+    #[test]
+    fn hysteresis() {
+        let mut zones = classifier();
+        assert_eq!(zones.classify(Distance(450)), Zone::Near);
+        // Within the hysteresis beyond either boundary of the zone
+        assert_eq!(zones.classify(Distance(519)), Zone::Near);
+        assert_eq!(zones.classify(Distance(80)), Zone::Near);
+        assert_eq!(zones.classify(Distance(520)), Zone::Mid);
+        assert_eq!(zones.classify(Distance(490)), Zone::Mid);
+        assert_eq!(zones.classify(Distance(479)), Zone::Near);
+        assert_eq!(zones.classify(Distance(2_000)), Zone::Far);
+        assert_eq!(zones.classify(Distance(1_480)), Zone::Far);
+        assert_eq!(zones.classify(Distance(1_479)), Zone::Mid);
+        zones.reset();
+        assert_eq!(zones.classify(Distance(510)), Zone::Mid);
+    }
+
+    fn bounds_must_increase() {
+        ZoneClassifier::new(Distance(100), Distance(100), Distance(1_500), Distance(20));
+    }
+}